@@ -0,0 +1,51 @@
+//! Controls a running launcher, e.g. from a hotkey daemon:
+//! `tigris-ctl show --query "gs rust"`
+
+use std::{env, process::exit};
+
+use tigris_core::features::control::{send_control_command, ControlCommand, ControlResponse};
+
+const USAGE: &str = "Usage: tigris-ctl <show [--query QUERY] | hide | toggle | query QUERY | ping>";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let command = match get_command(&args) {
+        Ok(command) => command,
+        Err(error) => {
+            eprintln!("{error}\n{USAGE}");
+            exit(2)
+        }
+    };
+
+    match send_control_command(&command) {
+        Ok(ControlResponse::Ok) => exit(0),
+        Ok(ControlResponse::Error { message }) => {
+            eprintln!("{message}");
+            exit(1)
+        }
+        Err(error) => {
+            eprintln!("{error}");
+            exit(1)
+        }
+    }
+}
+
+fn get_command(args: &[String]) -> Result<ControlCommand, String> {
+    let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+
+    match args.as_slice() {
+        ["show"] => Ok(ControlCommand::Show { query: None }),
+        ["show", "--query" | "-q", query] => Ok(ControlCommand::Show {
+            query: Some(query.to_string()),
+        }),
+        ["hide"] => Ok(ControlCommand::Hide),
+        ["toggle"] => Ok(ControlCommand::Toggle),
+        ["query", query] => Ok(ControlCommand::SetQuery {
+            query: query.to_string(),
+        }),
+        ["ping"] => Ok(ControlCommand::Ping),
+        [] => Err("Missing command".to_string()),
+        _ => Err(format!("Invalid arguments: {}", args.join(" "))),
+    }
+}
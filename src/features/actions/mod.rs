@@ -2,6 +2,15 @@ use std::path::{Path, PathBuf};
 
 use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "host")]
+use {
+    super::{utils::on_wayland, validation::is_valid_link},
+    std::error::Error,
+    std::fs::File,
+    std::io::Write,
+    std::process::{Command, Stdio},
+};
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ResultAction {
     pub action_type: ActionType,
@@ -12,6 +21,7 @@ pub struct ResultAction {
     pub open_app_action: Option<OpenAppAction>,
     pub open_form_action: Option<OpenFormAction>,
     pub run_extension_action: Option<RunExtensionAction>,
+    pub set_query_action: Option<SetQueryAction>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -23,6 +33,7 @@ pub enum ActionType {
     OpenForm,
     RunExtension,
     OpenSettings,
+    SetQuery,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -141,6 +152,15 @@ pub struct RunExtensionAction {
     pub args: Vec<String>,
 }
 
+/// Replaces the launcher's input instead of running something, e.g. to descend
+/// into a folder or complete a partial query
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SetQueryAction {
+    pub text: String,
+    /// Puts the keyword of the current query in front of the text
+    pub keep_keyword: bool,
+}
+
 // =================================================================
 // ==== Methods
 // =================================================================
@@ -156,6 +176,7 @@ impl ResultAction {
             open_app_action: None,
             open_form_action: None,
             run_extension_action: None,
+            set_query_action: None,
         }
     }
 
@@ -169,6 +190,7 @@ impl ResultAction {
             open_app_action: None,
             open_form_action: None,
             run_extension_action: None,
+            set_query_action: None,
         }
     }
 
@@ -182,6 +204,7 @@ impl ResultAction {
             open_app_action: None,
             open_form_action: None,
             run_extension_action: None,
+            set_query_action: None,
         }
     }
 
@@ -195,6 +218,7 @@ impl ResultAction {
             open_app_action: Some(action.to_owned()),
             open_form_action: None,
             run_extension_action: None,
+            set_query_action: None,
         }
     }
 
@@ -208,6 +232,7 @@ impl ResultAction {
             open_app_action: None,
             open_form_action: Some(action.to_owned()),
             run_extension_action: None,
+            set_query_action: None,
         }
     }
 
@@ -221,6 +246,7 @@ impl ResultAction {
             open_app_action: None,
             open_form_action: None,
             run_extension_action: Some(action.to_owned()),
+            set_query_action: None,
         }
     }
 
@@ -234,6 +260,21 @@ impl ResultAction {
             open_app_action: None,
             open_form_action: None,
             run_extension_action: None,
+            set_query_action: None,
+        }
+    }
+
+    pub fn new_set_query_action(action: &SetQueryAction) -> Self {
+        Self {
+            action_type: ActionType::SetQuery,
+            require_confirmation: false,
+            copy_text_action: None,
+            copy_image_action: None,
+            open_link_action: None,
+            open_app_action: None,
+            open_form_action: None,
+            run_extension_action: None,
+            set_query_action: Some(action.to_owned()),
         }
     }
 
@@ -275,6 +316,28 @@ impl OpenAppAction {
     }
 }
 
+impl SetQueryAction {
+    pub fn new(text: &str) -> Self {
+        Self {
+            text: text.to_owned(),
+            keep_keyword: true,
+        }
+    }
+
+    pub fn set_keep_keyword(mut self, keep_keyword: bool) -> Self {
+        self.keep_keyword = keep_keyword;
+        self
+    }
+
+    /// Query the launcher should show, given the keyword of the current one
+    pub fn get_query(&self, keyword: Option<&str>) -> String {
+        match keyword {
+            Some(keyword) if self.keep_keyword => format!("{keyword} {}", self.text),
+            _ => self.text.to_owned(),
+        }
+    }
+}
+
 impl OpenFormAction {
     pub fn new(extension_id: &str, form_id: &str, title: &str, button_text: &str) -> Self {
         Self {
@@ -454,6 +517,12 @@ impl FieldValidation {
     }
 }
 
+impl Default for FieldValidation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TextField {
     pub fn new(value: &str) -> Self {
         Self {
@@ -582,3 +651,112 @@ impl RunExtensionAction {
         self
     }
 }
+
+// =================================================================
+// ==== Host
+// =================================================================
+
+/// Runs the system side of an action. Forms, extension actions, settings and
+/// query changes depend on the launcher UI and return an error here.
+#[cfg(feature = "host")]
+pub fn run_action(action: &ResultAction) -> Result<(), Box<dyn Error>> {
+    match action.action_type {
+        ActionType::CopyText => {
+            let action = action
+                .copy_text_action
+                .as_ref()
+                .ok_or("Missing copy text action")?;
+
+            copy_text(&action.text)
+        }
+        ActionType::CopyImage => {
+            let action = action
+                .copy_image_action
+                .as_ref()
+                .ok_or("Missing copy image action")?;
+
+            copy_image(&action.image_path)
+        }
+        ActionType::OpenLink => {
+            let action = action
+                .open_link_action
+                .as_ref()
+                .ok_or("Missing open link action")?;
+
+            // `xdg-open` rejects `--`, so links are checked here in case they
+            // didn't go through the validator
+            if !is_valid_link(&action.link) {
+                return Err(format!("Invalid link: {}", action.link).into());
+            }
+
+            Command::new("xdg-open").arg(&action.link).spawn()?;
+            Ok(())
+        }
+        ActionType::OpenApp => {
+            let action = action
+                .open_app_action
+                .as_ref()
+                .ok_or("Missing open app action")?;
+
+            let desktop_id = action.path.file_stem().ok_or("Invalid desktop file path")?;
+
+            Command::new("gtk-launch").arg(desktop_id).spawn()?;
+            Ok(())
+        }
+        ActionType::OpenForm
+        | ActionType::RunExtension
+        | ActionType::OpenSettings
+        | ActionType::SetQuery => Err("This action must be handled by the launcher".into()),
+    }
+}
+
+/// Query the launcher should switch to for a `SetQuery` action, `None` for
+/// other actions. `keyword` is the keyword of the current query, if any.
+#[cfg(feature = "host")]
+pub fn get_action_query(action: &ResultAction, keyword: Option<&str>) -> Option<String> {
+    match action.action_type {
+        ActionType::SetQuery => action
+            .set_query_action
+            .as_ref()
+            .map(|action| action.get_query(keyword)),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "host")]
+fn copy_text(text: &str) -> Result<(), Box<dyn Error>> {
+    let mut command = if on_wayland() {
+        Command::new("wl-copy")
+    } else {
+        let mut command = Command::new("xclip");
+        command.args(["-selection", "clipboard"]);
+        command
+    };
+
+    let mut child = command.stdin(Stdio::piped()).spawn()?;
+
+    child
+        .stdin
+        .take()
+        .ok_or("Error opening clipboard stdin")?
+        .write_all(text.as_bytes())?;
+
+    child.wait()?;
+    Ok(())
+}
+
+#[cfg(feature = "host")]
+fn copy_image(image_path: &Path) -> Result<(), Box<dyn Error>> {
+    if on_wayland() {
+        Command::new("wl-copy")
+            .stdin(Stdio::from(File::open(image_path)?))
+            .status()?;
+    } else {
+        Command::new("xclip")
+            .args(["-selection", "clipboard", "-t", "image/png", "-i"])
+            .arg(image_path)
+            .status()?;
+    }
+
+    Ok(())
+}
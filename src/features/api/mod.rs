@@ -2,12 +2,38 @@ use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
 
+use super::{
+    actions::OpenFormAction, protocol::PROTOCOL_VERSION, search_results::SearchResult,
+    utils::get_locale,
+};
+
 #[cfg(feature = "extension")]
 use {
-    super::search_results::SearchResult, postcard::from_bytes, postcard::to_allocvec,
-    std::error::Error, std::io::Read, std::io::Write,
+    super::protocol::{
+        check_host_protocol_version, check_request_protocol_version, get_encoding, is_daemon_mode,
+        is_streaming_mode, read_frame, read_to_end_limited, write_frame, DaemonMessage,
+        DaemonResponse, Encoding, StreamFrame, MAX_FRAME_BYTES,
+    },
+    std::error::Error,
+    std::io::Write,
+    std::process::exit,
+    std::sync::atomic::{AtomicU64, Ordering},
+    std::sync::{mpsc, Mutex},
+    std::thread,
 };
 
+/// Id of the daemon request being handled
+#[cfg(feature = "extension")]
+static CURRENT_REQUEST: AtomicU64 = AtomicU64::new(0);
+
+/// Ids the host sent a cancel message for
+#[cfg(feature = "extension")]
+static CANCELLED_REQUESTS: Mutex<Vec<u64>> = Mutex::new(vec![]);
+
+/// Partial results kept until the end when the host doesn't stream
+#[cfg(feature = "extension")]
+static PENDING_RESULTS: Mutex<Vec<SearchResult>> = Mutex::new(vec![]);
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct FormResultsRequest {
     pub form_id: String,
@@ -24,46 +50,154 @@ pub struct FormResult {
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ExtensionRequest {
+    pub protocol_version: u32,
     pub request_type: RequestType,
     pub get_results_request: Option<GetResultsRequest>,
     pub run_action_request: Option<RunActionRequest>,
     pub form_results_request: Option<FormResultsRequest>,
+    pub run_job_request: Option<RunJobRequest>,
+}
+
+/// Reply of an extension to a request
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ExtensionResponse {
+    Results(Vec<SearchResult>),
+    /// The extension failed. Shown to the user as an error row
+    Error {
+        message: String,
+        details: Option<String>,
+    },
+    /// A message shown in place of results, like "Type to search"
+    Notice {
+        message: String,
+    },
+    /// Nothing matched. The hint is shown when there is one
+    Empty {
+        hint: Option<String>,
+    },
+    /// What the launcher should do after a `RunAction` or `FormResults` request
+    Action(ActionResponse),
+    /// Results the host can reuse for the same query for `ttl_secs`, overriding
+    /// the manifest's cache policy. A TTL of 0 means they can't be cached.
+    /// The launcher gets them as `Results`
+    CachedResults {
+        results: Vec<SearchResult>,
+        ttl_secs: u64,
+    },
 }
 
+/// Tells the launcher what happens after an extension action or form submission
 #[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ActionResponse {
+    /// Close the launcher window
+    Close,
+    /// Leave the launcher as it is
+    KeepOpen,
+    /// Replace the text in the search box
+    SetQuery {
+        query: String,
+    },
+    /// Show these results in place of the current ones
+    ShowResults(Vec<SearchResult>),
+    OpenForm(OpenFormAction),
+    ShowNotification {
+        title: String,
+        description: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum RequestType {
     GetResults,
     RunAction,
     FormResults,
+    RunJob,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct GetResultsRequest {
     pub search_text: String,
+    /// Only optional in JSON requests, which get the default context when it's
+    /// missing. Postcard is positional, so hosts using it must upgrade: older
+    /// ones are refused by the protocol version check.
+    #[serde(default)]
+    pub context: QueryContext,
+}
+
+/// What the launcher knows about the search besides its text, so extensions
+/// can tailor and trim their output
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QueryContext {
+    /// Keyword that routed the query to the extension
+    pub keyword: Option<String>,
+    /// Query as the user typed it, keyword included. Empty when the launcher
+    /// didn't set it, see [`QueryContext::new`]
+    pub raw_query: String,
+    /// Locale of the user, e.g. `en_US`
+    pub locale: String,
+    /// Most results the launcher will show, `None` if there's no limit
+    pub max_results: Option<usize>,
+    /// Result the user picked in the last search, if any
+    pub previous_selection: Option<SearchResult>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct RunActionRequest {
     pub action: String,
     pub args: Vec<String>,
+    /// Id of the extension whose result ran the action, `None` if the action
+    /// came from the launcher or one of this extension's own results
+    #[serde(default)]
+    pub caller_id: Option<String>,
+}
+
+/// Sent by the host scheduler for a job declared in the manifest
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RunJobRequest {
+    pub job_id: String,
 }
 
 impl ExtensionRequest {
+    /// Request with a default context, which has an empty `raw_query`
     pub fn new_get_results_request(search_text: &str) -> Self {
+        Self::new_get_results_request_with_context(search_text, &QueryContext::default())
+    }
+
+    pub fn new_get_results_request_with_context(search_text: &str, context: &QueryContext) -> Self {
         Self {
+            protocol_version: PROTOCOL_VERSION,
             request_type: RequestType::GetResults,
-            get_results_request: Some(GetResultsRequest::new(search_text)),
+            get_results_request: Some(GetResultsRequest::new_with_context(search_text, context)),
             run_action_request: None,
             form_results_request: None,
+            run_job_request: None,
         }
     }
 
     pub fn new_run_extension_action_request(action: &str, args: &Vec<String>) -> Self {
         Self {
+            protocol_version: PROTOCOL_VERSION,
             request_type: RequestType::RunAction,
             get_results_request: None,
             run_action_request: Some(RunActionRequest::new(action, args)),
             form_results_request: None,
+            run_job_request: None,
+        }
+    }
+
+    /// Action of another extension, run on behalf of `caller_id`
+    pub fn new_cross_extension_action_request(
+        caller_id: &str,
+        action: &str,
+        args: &Vec<String>,
+    ) -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            request_type: RequestType::RunAction,
+            get_results_request: None,
+            run_action_request: Some(RunActionRequest::new(action, args).set_caller_id(caller_id)),
+            form_results_request: None,
+            run_job_request: None,
         }
     }
 
@@ -73,6 +207,7 @@ impl ExtensionRequest {
         args: &Vec<String>,
     ) -> Self {
         Self {
+            protocol_version: PROTOCOL_VERSION,
             request_type: RequestType::FormResults,
             get_results_request: None,
             run_action_request: None,
@@ -81,14 +216,233 @@ impl ExtensionRequest {
                 results: results.to_owned(),
                 args: args.to_owned(),
             }),
+            run_job_request: None,
+        }
+    }
+
+    pub fn new_run_job_request(job_id: &str) -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            request_type: RequestType::RunJob,
+            get_results_request: None,
+            run_action_request: None,
+            form_results_request: None,
+            run_job_request: Some(RunJobRequest {
+                job_id: job_id.to_owned(),
+            }),
         }
     }
 }
 
+#[cfg(feature = "extension")]
+impl ExtensionRequest {
+    pub(crate) fn into_payload(self) -> Result<RequestPayload, Box<dyn Error>> {
+        let payload = match self.request_type {
+            RequestType::GetResults => self
+                .get_results_request
+                .map(|request| RequestPayload::GetResults(Box::new(request))),
+            RequestType::RunAction => self.run_action_request.map(RequestPayload::RunAction),
+            RequestType::FormResults => self.form_results_request.map(RequestPayload::FormResults),
+            RequestType::RunJob => self.run_job_request.map(RequestPayload::RunJob),
+        };
+
+        payload.ok_or_else(|| format!("Missing payload for {:?} request", self.request_type).into())
+    }
+}
+
+impl ExtensionResponse {
+    pub fn new_error(message: &str) -> Self {
+        Self::Error {
+            message: message.to_owned(),
+            details: None,
+        }
+    }
+
+    pub fn new_error_with_details(message: &str, details: &str) -> Self {
+        Self::Error {
+            message: message.to_owned(),
+            details: Some(details.to_owned()),
+        }
+    }
+
+    pub fn new_notice(message: &str) -> Self {
+        Self::Notice {
+            message: message.to_owned(),
+        }
+    }
+
+    pub fn new_empty() -> Self {
+        Self::Empty { hint: None }
+    }
+
+    pub fn new_empty_with_hint(hint: &str) -> Self {
+        Self::Empty {
+            hint: Some(hint.to_owned()),
+        }
+    }
+
+    pub fn new_cached_results(results: &[SearchResult], ttl_secs: u64) -> Self {
+        Self::CachedResults {
+            results: results.to_vec(),
+            ttl_secs,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, Self::Error { .. })
+    }
+
+    /// Results to show in the list. Only `Results` and `CachedResults` have any
+    pub fn get_results(&self) -> &[SearchResult] {
+        match self {
+            Self::Results(results) | Self::CachedResults { results, .. } => results,
+            _ => &[],
+        }
+    }
+
+    /// What the launcher should do after an action or form. Results are shown as
+    /// they are, errors are returned as their message and anything else closes
+    /// the launcher.
+    pub fn into_action_response(self) -> Result<ActionResponse, String> {
+        match self {
+            Self::Action(response) => Ok(response),
+            Self::Error { message, .. } => Err(message),
+            Self::Results(results) | Self::CachedResults { results, .. } => {
+                Ok(ActionResponse::ShowResults(results))
+            }
+            Self::Notice { .. } | Self::Empty { .. } => Ok(ActionResponse::Close),
+        }
+    }
+
+    /// Turns `CachedResults` into plain `Results`, returning its TTL
+    #[cfg(feature = "host")]
+    pub(crate) fn split_cache_ttl(self) -> (Self, Option<u64>) {
+        match self {
+            Self::CachedResults { results, ttl_secs } => (Self::Results(results), Some(ttl_secs)),
+            response => (response, None),
+        }
+    }
+
+    /// Puts results that were sent earlier in a stream in front of this final
+    /// response. Errors and notices replace them.
+    pub fn merge_partial_results(self, mut partial_results: Vec<SearchResult>) -> Self {
+        match self {
+            Self::Results(results) => {
+                partial_results.extend(results);
+                Self::Results(partial_results)
+            }
+            Self::CachedResults { results, ttl_secs } => {
+                partial_results.extend(results);
+                Self::CachedResults {
+                    results: partial_results,
+                    ttl_secs,
+                }
+            }
+            Self::Empty { .. } if !partial_results.is_empty() => Self::Results(partial_results),
+            response => response,
+        }
+    }
+}
+
+impl ActionResponse {
+    pub fn new_set_query(query: &str) -> Self {
+        Self::SetQuery {
+            query: query.to_owned(),
+        }
+    }
+
+    pub fn new_show_results(results: &[SearchResult]) -> Self {
+        Self::ShowResults(results.to_vec())
+    }
+
+    pub fn new_open_form(form: &OpenFormAction) -> Self {
+        Self::OpenForm(form.to_owned())
+    }
+
+    pub fn new_show_notification(title: &str, description: &str) -> Self {
+        Self::ShowNotification {
+            title: title.to_owned(),
+            description: description.to_owned(),
+        }
+    }
+}
+
+impl From<ActionResponse> for ExtensionResponse {
+    fn from(response: ActionResponse) -> Self {
+        Self::Action(response)
+    }
+}
+
+impl From<Vec<SearchResult>> for ExtensionResponse {
+    fn from(results: Vec<SearchResult>) -> Self {
+        Self::Results(results)
+    }
+}
+
 impl GetResultsRequest {
+    /// Request with a default context, which has an empty `raw_query`
     pub fn new(search_text: &str) -> Self {
+        Self::new_with_context(search_text, &QueryContext::default())
+    }
+
+    pub fn new_with_context(search_text: &str, context: &QueryContext) -> Self {
         Self {
             search_text: search_text.to_owned(),
+            context: context.to_owned(),
+        }
+    }
+}
+
+impl QueryContext {
+    /// `raw_query` is the whole query, e.g. `gh tigris` when the extension gets
+    /// `tigris` as the search text
+    pub fn new(raw_query: &str) -> Self {
+        Self {
+            raw_query: raw_query.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    pub fn set_keyword(mut self, keyword: &str) -> Self {
+        self.keyword = Some(keyword.to_owned());
+        self
+    }
+
+    pub fn set_locale(mut self, locale: &str) -> Self {
+        self.locale = locale.to_owned();
+        self
+    }
+
+    pub fn set_max_results(mut self, max_results: usize) -> Self {
+        self.max_results = Some(max_results);
+        self
+    }
+
+    pub fn set_previous_selection(mut self, result: &SearchResult) -> Self {
+        self.previous_selection = Some(result.to_owned());
+        self
+    }
+
+    /// Drops the results the launcher wouldn't show anyway
+    pub fn limit_results(&self, mut results: Vec<SearchResult>) -> Vec<SearchResult> {
+        if let Some(max_results) = self.max_results {
+            results.truncate(max_results);
+        }
+
+        results
+    }
+}
+
+/// Used when the host doesn't send a context. The locale is read from the
+/// environment the extension inherited from the host.
+impl Default for QueryContext {
+    fn default() -> Self {
+        Self {
+            keyword: None,
+            raw_query: String::new(),
+            locale: get_locale(),
+            max_results: None,
+            previous_selection: None,
         }
     }
 }
@@ -98,8 +452,14 @@ impl RunActionRequest {
         Self {
             action: action.to_owned(),
             args: args.to_owned(),
+            caller_id: None,
         }
     }
+
+    pub fn set_caller_id(mut self, caller_id: &str) -> Self {
+        self.caller_id = Some(caller_id.to_owned());
+        self
+    }
 }
 
 impl FormResultsRequest {
@@ -147,24 +507,273 @@ impl FormResultsRequest {
 // ==== API
 // =================================================================
 
+/// Implemented by extension binaries and passed to [`run`], which takes care of
+/// reading the request and sending the reply.
+///
+/// Implement `get_results`, or `get_response` to also reply with notices and
+/// empty states. Errors are sent to the launcher as an error row.
 #[cfg(feature = "extension")]
-pub fn return_search_results(results: &Vec<SearchResult>) {
-    use std::process::exit;
+pub trait Extension {
+    fn get_results(
+        &self,
+        _request: &GetResultsRequest,
+    ) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+        Ok(vec![])
+    }
+
+    fn get_response(
+        &self,
+        request: &GetResultsRequest,
+    ) -> Result<ExtensionResponse, Box<dyn Error>> {
+        Ok(ExtensionResponse::Results(self.get_results(request)?))
+    }
+
+    fn run_action(&self, _request: &RunActionRequest) -> Result<ActionResponse, Box<dyn Error>> {
+        Ok(ActionResponse::Close)
+    }
+
+    fn form_results(
+        &self,
+        _request: &FormResultsRequest,
+    ) -> Result<ActionResponse, Box<dyn Error>> {
+        Ok(ActionResponse::Close)
+    }
+
+    /// Runs a background job, like refreshing an index. Errors make the host
+    /// retry later with a backoff.
+    fn run_job(&self, _request: &RunJobRequest) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+/// Reads the request from stdin, dispatches it to the extension and exits.
+/// Handler errors are sent as an error response. Failing to read the request
+/// or send the reply exits with a non-zero code.
+///
+/// When the host starts the extension as a daemon it keeps answering framed
+/// requests until stdin is closed. Handlers must not print to stdout in that mode.
+#[cfg(feature = "extension")]
+pub fn run(extension: impl Extension) -> ! {
+    let result = if is_daemon_mode() {
+        run_daemon(|request| dispatch(&extension, request))
+    } else {
+        get_request().and_then(|request| write_response(&dispatch(&extension, request)))
+    };
 
-    let bytes = to_allocvec(results).expect("Error getting results as bytes");
-    std::io::stdout()
-        .write_all(&bytes)
-        .expect("Error sendind results");
+    match result {
+        Ok(()) => exit(0),
+        Err(error) => {
+            eprintln!("{error}");
+            exit(1)
+        }
+    }
+}
+
+/// Answers daemon requests with `handle` until the host closes stdin
+#[cfg(feature = "extension")]
+pub(crate) fn run_daemon(
+    mut handle: impl FnMut(ExtensionRequest) -> ExtensionResponse,
+) -> Result<(), Box<dyn Error>> {
+    check_env_protocol_version()?;
+    let encoding = get_encoding();
+
+    let (sender, requests) = mpsc::channel();
+
+    // Reading happens on its own thread so cancel messages arrive while a handler runs
+    thread::spawn(move || {
+        let mut stdin = std::io::stdin().lock();
+
+        loop {
+            match read_frame::<DaemonMessage>(&mut stdin, encoding, MAX_FRAME_BYTES) {
+                Ok(Some(DaemonMessage::Request(request))) => {
+                    if sender.send(Ok(*request)).is_err() {
+                        break;
+                    }
+                }
+                Ok(Some(DaemonMessage::Cancel(id))) => {
+                    CANCELLED_REQUESTS.lock().unwrap().push(id);
+                }
+                Ok(None) => break,
+                Err(error) => {
+                    let _ = sender.send(Err(error.to_string()));
+                    break;
+                }
+            }
+        }
+    });
+
+    for request in requests {
+        let frame = request?;
+        CURRENT_REQUEST.store(frame.id, Ordering::SeqCst);
+
+        let response = if is_request_cancelled() {
+            ExtensionResponse::new_error("Request was cancelled")
+        } else if let Err(error) = check_request_protocol_version(frame.request.protocol_version) {
+            ExtensionResponse::new_error(&error.to_string())
+        } else {
+            handle(frame.request)
+        };
 
+        CANCELLED_REQUESTS
+            .lock()
+            .unwrap()
+            .retain(|id| *id > frame.id);
+
+        write_frame(
+            &mut std::io::stdout().lock(),
+            encoding,
+            &DaemonResponse {
+                id: frame.id,
+                frame: StreamFrame::End(response),
+            },
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Whether the host cancelled the request being handled. Only daemons receive
+/// cancel messages, so this is always `false` for one-shot extensions.
+///
+/// Daemons handle one request at a time, so slow handlers must poll this and
+/// return early. The host restarts a daemon whose cancelled request doesn't end
+/// within the host's `CANCEL_GRACE`.
+#[cfg(feature = "extension")]
+pub fn is_request_cancelled() -> bool {
+    let current = CURRENT_REQUEST.load(Ordering::SeqCst);
+    CANCELLED_REQUESTS.lock().unwrap().contains(&current)
+}
+
+/// Payload of a request, taken from the field its type says it uses. Shared by
+/// the sync and async dispatchers so both handle the same request types.
+#[cfg(feature = "extension")]
+pub(crate) enum RequestPayload {
+    GetResults(Box<GetResultsRequest>),
+    RunAction(RunActionRequest),
+    FormResults(FormResultsRequest),
+    RunJob(RunJobRequest),
+}
+
+/// Calls the handler for the request and turns its errors into an error response
+#[cfg(feature = "extension")]
+pub(crate) fn dispatch(extension: &impl Extension, request: ExtensionRequest) -> ExtensionResponse {
+    let response = request.into_payload().and_then(|payload| match payload {
+        RequestPayload::GetResults(request) => extension.get_response(&request),
+        RequestPayload::RunAction(request) => extension.run_action(&request).map(Into::into),
+        RequestPayload::FormResults(request) => extension.form_results(&request).map(Into::into),
+        RequestPayload::RunJob(request) => extension
+            .run_job(&request)
+            .map(|_| ExtensionResponse::new_empty()),
+    });
+
+    get_handler_response(response)
+}
+
+/// Reply for what a handler returned. Errors are also printed to stderr
+#[cfg(feature = "extension")]
+pub(crate) fn get_handler_response(
+    response: Result<ExtensionResponse, Box<dyn Error>>,
+) -> ExtensionResponse {
+    response.unwrap_or_else(|error| {
+        eprintln!("{error}");
+        ExtensionResponse::new_error(&error.to_string())
+    })
+}
+
+/// Sends results before the handler is done so the launcher can show them right
+/// away. Whatever the handler returns at the end is added after these. Hosts
+/// that don't stream get everything at once when the handler finishes.
+#[cfg(feature = "extension")]
+pub fn send_partial_results(results: &[SearchResult]) -> Result<(), Box<dyn Error>> {
+    if is_daemon_mode() {
+        write_frame(
+            &mut std::io::stdout().lock(),
+            get_encoding(),
+            &DaemonResponse {
+                id: CURRENT_REQUEST.load(Ordering::SeqCst),
+                frame: StreamFrame::Batch(results.to_vec()),
+            },
+        )
+    } else if is_streaming_mode() {
+        write_frame(
+            &mut std::io::stdout().lock(),
+            get_encoding(),
+            &StreamFrame::Batch(results.to_vec()),
+        )
+    } else {
+        PENDING_RESULTS.lock().unwrap().extend_from_slice(results);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "extension")]
+fn write_response(response: &ExtensionResponse) -> Result<(), Box<dyn Error>> {
+    let mut stdout = std::io::stdout().lock();
+    let encoding = get_encoding();
+
+    if is_streaming_mode() {
+        return write_frame(
+            &mut stdout,
+            encoding,
+            &StreamFrame::End(response.to_owned()),
+        );
+    }
+
+    let response = response
+        .to_owned()
+        .merge_partial_results(take_pending_results());
+
+    stdout.write_all(&encoding.encode(&response)?)?;
+
+    if encoding == Encoding::Json {
+        stdout.write_all(b"\n")?;
+    }
+
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Results buffered by [`send_partial_results`] that weren't sent yet
+#[cfg(feature = "extension")]
+pub(crate) fn take_pending_results() -> Vec<SearchResult> {
+    std::mem::take(&mut *PENDING_RESULTS.lock().unwrap())
+}
+
+#[cfg(feature = "extension")]
+pub fn return_response(response: &ExtensionResponse) {
+    write_response(response).expect("Error sending response");
     exit(0)
 }
 
+#[cfg(feature = "extension")]
+pub fn return_search_results(results: &[SearchResult]) {
+    return_response(&ExtensionResponse::Results(results.to_vec()))
+}
+
+#[cfg(feature = "extension")]
+pub fn return_error(message: &str) {
+    return_response(&ExtensionResponse::new_error(message))
+}
+
 #[cfg(feature = "extension")]
 pub fn get_request() -> Result<ExtensionRequest, Box<dyn Error>> {
-    let mut buffer: Vec<u8> = vec![];
-    std::io::stdin().read_to_end(&mut buffer)?;
+    check_env_protocol_version()?;
 
-    let request: ExtensionRequest = from_bytes(&buffer)?;
+    let buffer = read_to_end_limited(std::io::stdin().lock(), MAX_FRAME_BYTES)?;
+    let request: ExtensionRequest = get_encoding().decode(&buffer)?;
+    check_request_protocol_version(request.protocol_version)?;
 
     Ok(request)
 }
+
+/// Postcard requests are checked before decoding since a request in another
+/// format would only decode to garbage. JSON requests carry their version in a
+/// named field, so they can be typed by hand without setting the environment.
+#[cfg(feature = "extension")]
+pub(crate) fn check_env_protocol_version() -> Result<(), Box<dyn Error>> {
+    if get_encoding() == Encoding::Postcard {
+        check_host_protocol_version()?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,152 @@
+use std::{error::Error, process::exit};
+
+use tokio::{
+    io::AsyncWriteExt,
+    runtime::{Builder, Runtime},
+};
+
+use super::{
+    api::{
+        get_handler_response, get_request, run_daemon, take_pending_results, ActionResponse,
+        ExtensionRequest, ExtensionResponse, FormResultsRequest, GetResultsRequest, RequestPayload,
+        RunActionRequest, RunJobRequest,
+    },
+    protocol::{
+        encode_frame, get_encoding, is_daemon_mode, is_streaming_mode, Encoding, StreamFrame,
+    },
+    search_results::SearchResult,
+};
+
+/// Same as [`Extension`](super::api::Extension) but with `async` handlers, so
+/// lookups can run concurrently before replying. Handlers run on a single
+/// threaded runtime, so their futures don't have to be `Send`.
+#[allow(async_fn_in_trait)]
+pub trait AsyncExtension {
+    async fn get_results(
+        &self,
+        _request: &GetResultsRequest,
+    ) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+        Ok(vec![])
+    }
+
+    async fn get_response(
+        &self,
+        request: &GetResultsRequest,
+    ) -> Result<ExtensionResponse, Box<dyn Error>> {
+        Ok(ExtensionResponse::Results(self.get_results(request).await?))
+    }
+
+    async fn run_action(
+        &self,
+        _request: &RunActionRequest,
+    ) -> Result<ActionResponse, Box<dyn Error>> {
+        Ok(ActionResponse::Close)
+    }
+
+    async fn form_results(
+        &self,
+        _request: &FormResultsRequest,
+    ) -> Result<ActionResponse, Box<dyn Error>> {
+        Ok(ActionResponse::Close)
+    }
+
+    async fn run_job(&self, _request: &RunJobRequest) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+/// Async version of [`run`](super::api::run). It starts its own runtime, so it
+/// has to be called from a plain `fn main` and not from `#[tokio::main]`.
+pub fn run_async(extension: impl AsyncExtension) -> ! {
+    let result = get_runtime().and_then(|runtime| {
+        if is_daemon_mode() {
+            run_daemon(|request| runtime.block_on(dispatch_async(&extension, request)))
+        } else {
+            runtime.block_on(async {
+                let request = get_request_async().await?;
+                write_response_async(&dispatch_async(&extension, request).await).await
+            })
+        }
+    });
+
+    match result {
+        Ok(()) => exit(0),
+        Err(error) => {
+            eprintln!("{error}");
+            exit(1)
+        }
+    }
+}
+
+/// Runtime used by [`run_async`]. Extensions can enable more tokio features
+/// (`fs`, `net`, `time`...) in their own Cargo.toml to use them in handlers.
+fn get_runtime() -> Result<Runtime, Box<dyn Error>> {
+    Ok(Builder::new_current_thread().enable_all().build()?)
+}
+
+async fn dispatch_async(
+    extension: &impl AsyncExtension,
+    request: ExtensionRequest,
+) -> ExtensionResponse {
+    let response = match request.into_payload() {
+        Ok(RequestPayload::GetResults(request)) => extension.get_response(&request).await,
+        Ok(RequestPayload::RunAction(request)) => {
+            extension.run_action(&request).await.map(Into::into)
+        }
+        Ok(RequestPayload::FormResults(request)) => {
+            extension.form_results(&request).await.map(Into::into)
+        }
+        Ok(RequestPayload::RunJob(request)) => extension
+            .run_job(&request)
+            .await
+            .map(|_| ExtensionResponse::new_empty()),
+        Err(error) => Err(error),
+    };
+
+    get_handler_response(response)
+}
+
+/// Async version of [`get_request`](super::api::get_request), which runs on a blocking thread so both
+/// read and check requests the same way
+pub async fn get_request_async() -> Result<ExtensionRequest, Box<dyn Error>> {
+    // Errors aren't `Send`, so only their message crosses threads
+    tokio::task::spawn_blocking(|| get_request().map_err(|e| e.to_string()))
+        .await?
+        .map_err(|e| e.into())
+}
+
+/// Writes the reply to a one-shot request, including results sent earlier
+/// with `send_partial_results`.
+pub async fn write_response_async(response: &ExtensionResponse) -> Result<(), Box<dyn Error>> {
+    let encoding = get_encoding();
+
+    let bytes = if is_streaming_mode() {
+        encode_frame(encoding, &StreamFrame::End(response.to_owned()))?
+    } else {
+        let response = response
+            .to_owned()
+            .merge_partial_results(take_pending_results());
+
+        let mut bytes = encoding.encode(&response)?;
+
+        if encoding == Encoding::Json {
+            bytes.push(b'\n');
+        }
+
+        bytes
+    };
+
+    let mut stdout = tokio::io::stdout();
+    stdout.write_all(&bytes).await?;
+    stdout.flush().await?;
+
+    Ok(())
+}
+
+pub async fn return_search_results_async(results: &[SearchResult]) {
+    write_response_async(&ExtensionResponse::Results(results.to_vec()))
+        .await
+        .expect("Error sending response");
+
+    exit(0)
+}
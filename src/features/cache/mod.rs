@@ -0,0 +1,322 @@
+//! Keeps the responses of extensions so queries repeated within their TTL
+//! don't start the extension again.
+//!
+//! Entries are keyed by the extension id and the whole postcard encoded
+//! [`GetResultsRequest`], not just the search text. A query with the same text
+//! misses the cache if anything else in the request changed, including the
+//! [`QueryContext`](super::api::QueryContext) and its `previous_selection`.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use postcard::{from_bytes, to_allocvec};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    api::{ExtensionResponse, GetResultsRequest},
+    paths::get_result_cache_path,
+};
+
+pub const DEFAULT_MAX_ENTRIES: usize = 1000;
+
+/// Cache shared by the runners of every extension
+pub type SharedCache = Arc<Mutex<ResultCache>>;
+
+/// Responses of extensions kept by extension id and request. See
+/// [`ExtensionRunner::set_cache`](super::runner::ExtensionRunner::set_cache).
+#[derive(Debug, Clone)]
+pub struct ResultCache {
+    entries: HashMap<(String, Vec<u8>), CacheEntry>,
+    max_entries: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CacheEntry {
+    extension_id: String,
+    /// Postcard encoded `GetResultsRequest`
+    request: Vec<u8>,
+    response: ExtensionResponse,
+    /// Unix time in milliseconds
+    expires_at: u64,
+    persist: bool,
+}
+
+// =================================================================
+// ==== Methods
+// =================================================================
+
+impl ResultCache {
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            max_entries: DEFAULT_MAX_ENTRIES,
+        }
+    }
+
+    /// Cache with the entries saved by [`ResultCache::save`] that haven't
+    /// expired. Starts empty if there's nothing saved or it can't be read.
+    pub fn load() -> Self {
+        let mut cache = Self::new();
+
+        if let Ok(entries) = read_entries() {
+            let now = get_unix_millis();
+
+            for entry in entries.into_iter().filter(|e| e.expires_at > now) {
+                let key = (entry.extension_id.to_owned(), entry.request.to_owned());
+                cache.entries.insert(key, entry);
+            }
+        }
+
+        cache
+    }
+
+    /// Writes the entries of extensions with `persist` in their cache policy
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let now = get_unix_millis();
+
+        let entries: Vec<&CacheEntry> = self
+            .entries
+            .values()
+            .filter(|e| e.persist && e.expires_at > now)
+            .collect();
+
+        fs::write(get_result_cache_path()?, to_allocvec(&entries)?)?;
+        Ok(())
+    }
+
+    pub fn set_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    pub fn into_shared(self) -> SharedCache {
+        Arc::new(Mutex::new(self))
+    }
+
+    pub fn get(
+        &self,
+        extension_id: &str,
+        request: &GetResultsRequest,
+    ) -> Option<ExtensionResponse> {
+        self.entries
+            .get(&get_key(extension_id, request)?)
+            .filter(|entry| entry.expires_at > get_unix_millis())
+            .map(|entry| entry.response.to_owned())
+    }
+
+    /// Stores a response, making room by dropping expired entries first and
+    /// then the ones closest to expiring
+    pub fn insert(
+        &mut self,
+        extension_id: &str,
+        request: &GetResultsRequest,
+        response: &ExtensionResponse,
+        ttl: Duration,
+        persist: bool,
+    ) {
+        if self.max_entries == 0 {
+            return;
+        }
+
+        let Some(key) = get_key(extension_id, request) else {
+            return;
+        };
+
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.max_entries {
+            self.remove_expired();
+        }
+
+        while !self.entries.contains_key(&key) && self.entries.len() >= self.max_entries {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.expires_at)
+                .map(|(key, _)| key.to_owned());
+
+            match oldest {
+                Some(oldest) => self.entries.remove(&oldest),
+                None => break,
+            };
+        }
+
+        let expires_at = get_unix_millis().saturating_add(ttl.as_millis() as u64);
+
+        self.entries.insert(
+            key.to_owned(),
+            CacheEntry {
+                extension_id: extension_id.to_owned(),
+                request: key.1,
+                response: response.to_owned(),
+                expires_at,
+                persist,
+            },
+        );
+    }
+
+    /// Drops every entry of an extension, e.g. after its settings change
+    pub fn invalidate(&mut self, extension_id: &str) {
+        self.entries.retain(|(id, _), _| id != extension_id);
+    }
+
+    pub fn remove_expired(&mut self) {
+        let now = get_unix_millis();
+        self.entries.retain(|_, entry| entry.expires_at > now);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Default for ResultCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn get_key(extension_id: &str, request: &GetResultsRequest) -> Option<(String, Vec<u8>)> {
+    Some((extension_id.to_owned(), to_allocvec(request).ok()?))
+}
+
+fn read_entries() -> Result<Vec<CacheEntry>, Box<dyn Error>> {
+    let bytes = fs::read(get_result_cache_path()?)?;
+    Ok(from_bytes(&bytes)?)
+}
+
+fn get_unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::features::{api::QueryContext, search_results::SearchResult};
+
+    use super::*;
+
+    const LONG_TTL: Duration = Duration::from_secs(600);
+
+    fn get_request(search_text: &str) -> GetResultsRequest {
+        GetResultsRequest::new(search_text)
+    }
+
+    /// Responses are errors carrying a label, so they're easy to tell apart
+    fn insert(cache: &mut ResultCache, extension_id: &str, search_text: &str, ttl: Duration) {
+        let response = ExtensionResponse::new_error(search_text);
+        cache.insert(
+            extension_id,
+            &get_request(search_text),
+            &response,
+            ttl,
+            false,
+        );
+    }
+
+    fn get_label(
+        cache: &ResultCache,
+        extension_id: &str,
+        request: &GetResultsRequest,
+    ) -> Option<String> {
+        match cache.get(extension_id, request)? {
+            ExtensionResponse::Error { message, .. } => Some(message),
+            response => panic!("Unexpected response {response:?}"),
+        }
+    }
+
+    fn has(cache: &ResultCache, extension_id: &str, search_text: &str) -> bool {
+        get_label(cache, extension_id, &get_request(search_text)).is_some()
+    }
+
+    #[test]
+    fn responses_are_reused_within_their_ttl() {
+        let mut cache = ResultCache::new();
+        insert(&mut cache, "ext", "fresh", LONG_TTL);
+        insert(&mut cache, "ext", "expired", Duration::ZERO);
+
+        assert_eq!(
+            get_label(&cache, "ext", &get_request("fresh")),
+            Some("fresh".into())
+        );
+        assert!(!has(&cache, "ext", "expired"));
+        assert!(!has(&cache, "other", "fresh"));
+
+        cache.remove_expired();
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn the_whole_request_is_the_key() {
+        let mut cache = ResultCache::new();
+        insert(&mut cache, "ext", "text", LONG_TTL);
+
+        let with_locale = QueryContext::default().set_locale("de_DE");
+        let with_selection =
+            QueryContext::default().set_previous_selection(&SearchResult::new("a"));
+
+        for context in [with_locale, with_selection] {
+            let request = GetResultsRequest::new_with_context("text", &context);
+            assert_eq!(get_label(&cache, "ext", &request), None, "{context:?}");
+        }
+
+        assert!(has(&cache, "ext", "text"));
+    }
+
+    #[test]
+    fn expired_entries_are_evicted_first_then_the_ones_expiring_soonest() {
+        let mut cache = ResultCache::new().set_max_entries(3);
+        insert(&mut cache, "ext", "long", LONG_TTL * 2);
+        insert(&mut cache, "ext", "expired", Duration::ZERO);
+        insert(&mut cache, "ext", "short", LONG_TTL);
+
+        insert(&mut cache, "ext", "first", LONG_TTL * 3);
+        assert_eq!(cache.len(), 3);
+        assert!(has(&cache, "ext", "long") && has(&cache, "ext", "short"));
+
+        insert(&mut cache, "ext", "second", LONG_TTL * 3);
+        assert_eq!(cache.len(), 3);
+        assert!(!has(&cache, "ext", "short"));
+        assert!(has(&cache, "ext", "long"));
+        assert!(has(&cache, "ext", "first") && has(&cache, "ext", "second"));
+
+        // Replacing an entry doesn't need room
+        insert(&mut cache, "ext", "long", LONG_TTL);
+        assert_eq!(cache.len(), 3);
+        assert!(has(&cache, "ext", "first") && has(&cache, "ext", "second"));
+    }
+
+    #[test]
+    fn nothing_is_kept_without_room() {
+        let mut cache = ResultCache::new().set_max_entries(0);
+        insert(&mut cache, "ext", "text", LONG_TTL);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn invalidate_only_drops_the_entries_of_the_extension() {
+        let mut cache = ResultCache::new();
+        insert(&mut cache, "ext", "a", LONG_TTL);
+        insert(&mut cache, "ext", "b", LONG_TTL);
+        insert(&mut cache, "other", "a", LONG_TTL);
+
+        cache.invalidate("ext");
+
+        assert!(!has(&cache, "ext", "a") && !has(&cache, "ext", "b"));
+        assert!(has(&cache, "other", "a"));
+        assert_eq!(cache.len(), 1);
+    }
+}
@@ -0,0 +1,86 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc, Mutex,
+};
+
+/// Shared flag used to stop a running extension request early
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+/// Hands out ids for searches so that results from a search that was
+/// superseded by a newer one can be thrown away.
+#[derive(Debug, Clone, Default)]
+pub struct SearchTracker {
+    latest: Arc<AtomicU64>,
+    cancel: Arc<Mutex<CancelToken>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchTicket {
+    pub id: u64,
+    pub cancel: CancelToken,
+    latest: Arc<AtomicU64>,
+}
+
+// =================================================================
+// ==== Methods
+// =================================================================
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+impl SearchTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts a new search and cancels the one before it
+    pub fn start(&self) -> SearchTicket {
+        let id = self.latest.fetch_add(1, Ordering::SeqCst) + 1;
+        let cancel = CancelToken::new();
+
+        let previous = std::mem::replace(&mut *self.cancel.lock().unwrap(), cancel.clone());
+        previous.cancel();
+
+        SearchTicket {
+            id,
+            cancel,
+            latest: Arc::clone(&self.latest),
+        }
+    }
+
+    /// Cancels the running search without starting a new one
+    pub fn cancel(&self) {
+        self.latest.fetch_add(1, Ordering::SeqCst);
+        self.cancel.lock().unwrap().cancel();
+    }
+}
+
+impl SearchTicket {
+    /// Whether no newer search was started since this one
+    pub fn is_current(&self) -> bool {
+        self.latest.load(Ordering::SeqCst) == self.id
+    }
+
+    /// Returns the results only if they belong to the latest search
+    pub fn accept<T>(&self, results: T) -> Option<T> {
+        if self.is_current() {
+            Some(results)
+        } else {
+            None
+        }
+    }
+}
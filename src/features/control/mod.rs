@@ -0,0 +1,216 @@
+use std::{
+    error::Error,
+    fs,
+    io::BufReader,
+    os::unix::{
+        fs::PermissionsExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    paths::get_control_socket_path,
+    protocol::{read_frame, write_frame, Encoding},
+};
+
+/// Version of the control messages, separate from the extension protocol.
+/// Bumped only when `ControlRequest`, `ControlCommand` or `ControlResponse` change.
+pub const CONTROL_PROTOCOL_VERSION: u32 = 1;
+
+/// Commands and replies are tiny, anything bigger is a confused client
+pub const MAX_CONTROL_FRAME_BYTES: usize = 64 * 1024;
+
+/// How long either side waits for the other before giving up on a connection
+pub const CONTROL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What a script or hotkey daemon can ask a running launcher to do
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ControlCommand {
+    /// Show the window, with the query already typed if there's one
+    Show {
+        query: Option<String>,
+    },
+    Hide,
+    Toggle,
+    /// Replace the query without showing or hiding the window
+    SetQuery {
+        query: String,
+    },
+    /// Check that the launcher is running
+    Ping,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ControlResponse {
+    Ok,
+    Error { message: String },
+}
+
+/// Frame sent by the client, one per connection
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ControlRequest {
+    pub protocol_version: u32,
+    pub command: ControlCommand,
+}
+
+/// Listens for commands on a Unix socket in the runtime dir. The socket is
+/// removed on drop.
+///
+/// Handlers run on the thread calling [`ControlServer::serve`], so launchers
+/// with their own event loop usually serve from a thread and forward commands
+/// through a channel.
+#[derive(Debug)]
+pub struct ControlServer {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+// =================================================================
+// ==== Methods
+// =================================================================
+
+impl ControlServer {
+    pub fn bind() -> Result<Self, Box<dyn Error>> {
+        Self::bind_at(&get_control_socket_path()?)
+    }
+
+    /// Listens on `path`, replacing a socket left behind by a launcher that
+    /// didn't exit cleanly. Fails if another launcher is still listening.
+    pub fn bind_at(path: &Path) -> Result<Self, Box<dyn Error>> {
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(
+                    format!("A launcher is already listening on {}", path.display()).into(),
+                );
+            }
+
+            fs::remove_file(path)?;
+        }
+
+        let listener = UnixListener::bind(path)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+
+        Ok(Self {
+            listener,
+            path: path.to_owned(),
+        })
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Waits for the next client and replies with what `handle` returns
+    pub fn handle_next(
+        &self,
+        handle: impl FnOnce(ControlCommand) -> ControlResponse,
+    ) -> Result<(), Box<dyn Error>> {
+        let (stream, _) = self.listener.accept()?;
+        handle_client(&stream, handle)
+    }
+
+    /// Handles clients until the socket fails. Errors of a single client, like
+    /// one that times out, are passed to `on_error` and don't stop the server.
+    pub fn serve(
+        &self,
+        mut handle: impl FnMut(ControlCommand) -> ControlResponse,
+        mut on_error: impl FnMut(Box<dyn Error>),
+    ) -> Result<(), Box<dyn Error>> {
+        loop {
+            let (stream, _) = self.listener.accept()?;
+
+            if let Err(error) = handle_client(&stream, &mut handle) {
+                on_error(error);
+            }
+        }
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl ControlRequest {
+    pub fn new(command: &ControlCommand) -> Self {
+        Self {
+            protocol_version: CONTROL_PROTOCOL_VERSION,
+            command: command.to_owned(),
+        }
+    }
+}
+
+impl ControlResponse {
+    pub fn new_error(message: &str) -> Self {
+        Self::Error {
+            message: message.to_owned(),
+        }
+    }
+}
+
+fn handle_client(
+    stream: &UnixStream,
+    handle: impl FnOnce(ControlCommand) -> ControlResponse,
+) -> Result<(), Box<dyn Error>> {
+    stream.set_read_timeout(Some(CONTROL_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONTROL_TIMEOUT))?;
+
+    // Closing without a command is how `bind_at` checks for a running launcher
+    let Some(request) = read_frame::<ControlRequest>(
+        &mut BufReader::new(stream),
+        Encoding::Postcard,
+        MAX_CONTROL_FRAME_BYTES,
+    )?
+    else {
+        return Ok(());
+    };
+
+    let response = if request.protocol_version == CONTROL_PROTOCOL_VERSION {
+        handle(request.command)
+    } else {
+        ControlResponse::new_error(&format!(
+            "Client speaks protocol {}, launcher speaks {CONTROL_PROTOCOL_VERSION}",
+            request.protocol_version
+        ))
+    };
+
+    write_frame(&mut &*stream, Encoding::Postcard, &response)
+}
+
+// =================================================================
+// ==== Client
+// =================================================================
+
+/// Sends a command to the launcher running for this user
+pub fn send_control_command(command: &ControlCommand) -> Result<ControlResponse, Box<dyn Error>> {
+    send_control_command_to(&get_control_socket_path()?, command)
+}
+
+pub fn send_control_command_to(
+    path: &Path,
+    command: &ControlCommand,
+) -> Result<ControlResponse, Box<dyn Error>> {
+    let stream = UnixStream::connect(path)
+        .map_err(|e| format!("Launcher is not running on {}: {e}", path.display()))?;
+
+    stream.set_read_timeout(Some(CONTROL_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONTROL_TIMEOUT))?;
+
+    write_frame(
+        &mut &stream,
+        Encoding::Postcard,
+        &ControlRequest::new(command),
+    )?;
+
+    read_frame(
+        &mut BufReader::new(&stream),
+        Encoding::Postcard,
+        MAX_CONTROL_FRAME_BYTES,
+    )?
+    .ok_or_else(|| "Launcher closed the connection without replying".into())
+}
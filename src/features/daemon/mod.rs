@@ -0,0 +1,472 @@
+use std::{
+    io::{BufReader, Read},
+    process::{Child, ChildStdin},
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::{
+    api::{ActionResponse, ExtensionRequest, ExtensionResponse, FormResult},
+    cancellation::CancelToken,
+    protocol::{
+        read_frame, write_frame, DaemonMessage, DaemonRequest, DaemonResponse, StreamFrame,
+        DAEMON_ENV,
+    },
+    runner::{
+        get_action_response, wait_with_deadline, ExtensionRunner, RunnerError, POLL_INTERVAL,
+    },
+    search_results::SearchResult,
+    validation::ResultValidator,
+};
+
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How long a daemon gets to exit on its own after stdin is closed
+const SHUTDOWN_GRACE: Duration = Duration::from_millis(200);
+
+/// How long a cancelled request gets to end before the next request restarts
+/// the daemon. Handlers end early by checking `is_request_cancelled`.
+pub const CANCEL_GRACE: Duration = Duration::from_millis(250);
+
+/// Only the end of stderr is kept for error messages
+const MAX_STDERR_BYTES: usize = 16 * 1024;
+
+/// Keeps an extension process alive between requests. The process is started on
+/// the first request, restarted if it crashes and stopped when idle or dropped.
+///
+/// Daemons handle one request at a time, so a cancelled request that is still
+/// running when the next one is sent gets `CANCEL_GRACE` from its cancellation
+/// to end. After that the daemon is restarted, so the new request doesn't wait
+/// behind it.
+pub struct ExtensionDaemon {
+    pub runner: ExtensionRunner,
+    pub idle_timeout: Duration,
+    process: Option<DaemonProcess>,
+    next_id: u64,
+    last_used: Instant,
+    /// Cancelled requests that didn't end yet, with when they have to
+    cancelled: Vec<(u64, Instant)>,
+}
+
+struct DaemonProcess {
+    child: Child,
+    stdin: ChildStdin,
+    /// Frames read from stdout, or why reading stopped
+    responses: Receiver<Result<DaemonResponse, String>>,
+    stderr: Arc<Mutex<Vec<u8>>>,
+}
+
+// =================================================================
+// ==== Methods
+// =================================================================
+
+impl ExtensionDaemon {
+    pub fn new(runner: ExtensionRunner) -> Self {
+        Self {
+            runner,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            process: None,
+            next_id: 0,
+            last_used: Instant::now(),
+            cancelled: vec![],
+        }
+    }
+
+    pub fn set_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.process.is_some()
+    }
+
+    pub fn get_results(&mut self, search_text: &str) -> Result<ExtensionResponse, RunnerError> {
+        self.send(&ExtensionRequest::new_get_results_request(search_text))
+    }
+
+    /// Same as [`ExtensionDaemon::get_results`] but gives up as soon as the token
+    /// is cancelled and tells the daemon to skip the request.
+    pub fn get_results_with_cancel(
+        &mut self,
+        search_text: &str,
+        cancel: &CancelToken,
+    ) -> Result<ExtensionResponse, RunnerError> {
+        self.send_with_cancel(
+            &ExtensionRequest::new_get_results_request(search_text),
+            cancel,
+        )
+    }
+
+    pub fn run_action(
+        &mut self,
+        action: &str,
+        args: &Vec<String>,
+    ) -> Result<ActionResponse, RunnerError> {
+        get_action_response(
+            self.send(&ExtensionRequest::new_run_extension_action_request(
+                action, args,
+            ))?,
+        )
+    }
+
+    /// Same as [`ExtensionRunner::run_action_from`]
+    pub fn run_action_from(
+        &mut self,
+        caller_id: &str,
+        action: &str,
+        args: &Vec<String>,
+    ) -> Result<ActionResponse, RunnerError> {
+        self.runner.check_action_caller(action, caller_id)?;
+
+        get_action_response(
+            self.send(&ExtensionRequest::new_cross_extension_action_request(
+                caller_id, action, args,
+            ))?,
+        )
+    }
+
+    pub fn send_form_results(
+        &mut self,
+        form_id: &str,
+        results: &Vec<FormResult>,
+        args: &Vec<String>,
+    ) -> Result<ActionResponse, RunnerError> {
+        get_action_response(self.send(&ExtensionRequest::new_form_results_request(
+            form_id, results, args,
+        ))?)
+    }
+
+    /// Sends a request to the daemon, starting it if needed, and waits for the
+    /// matching reply. A daemon that misses the deadline is killed.
+    pub fn send(&mut self, request: &ExtensionRequest) -> Result<ExtensionResponse, RunnerError> {
+        self.send_with_cancel(request, &CancelToken::new())
+    }
+
+    pub fn send_with_cancel(
+        &mut self,
+        request: &ExtensionRequest,
+        cancel: &CancelToken,
+    ) -> Result<ExtensionResponse, RunnerError> {
+        self.send_streaming(request, cancel, &mut |_| {})
+    }
+
+    /// Calls `on_batch` with each batch of results as soon as the daemon sends it
+    /// and returns all of them merged.
+    pub fn stream_results(
+        &mut self,
+        search_text: &str,
+        cancel: &CancelToken,
+        on_batch: impl FnMut(&[SearchResult]),
+    ) -> Result<ExtensionResponse, RunnerError> {
+        self.stream_request(
+            &ExtensionRequest::new_get_results_request(search_text),
+            cancel,
+            on_batch,
+        )
+    }
+
+    pub fn stream_request(
+        &mut self,
+        request: &ExtensionRequest,
+        cancel: &CancelToken,
+        mut on_batch: impl FnMut(&[SearchResult]),
+    ) -> Result<ExtensionResponse, RunnerError> {
+        self.send_streaming(request, cancel, &mut on_batch)
+    }
+
+    fn send_streaming(
+        &mut self,
+        request: &ExtensionRequest,
+        cancel: &CancelToken,
+        on_batch: &mut dyn FnMut(&[SearchResult]),
+    ) -> Result<ExtensionResponse, RunnerError> {
+        if let Some(response) = self.runner.get_cached(request) {
+            on_batch(response.get_results());
+            return Ok(response);
+        }
+
+        self.finish_cancelled();
+        self.last_used = Instant::now();
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let frame = DaemonMessage::Request(Box::new(DaemonRequest {
+            id,
+            request: request.to_owned(),
+        }));
+
+        if self.write(&frame).is_err() {
+            // The daemon exited since the last request, give it one fresh start
+            self.stop();
+            self.write(&frame)?;
+        }
+
+        let response = self.wait_for(id, cancel, on_batch)?;
+        Ok(self.runner.update_cache(request, response))
+    }
+
+    /// Stops the daemon if it hasn't been used for longer than the idle timeout.
+    /// Meant to be called periodically by the launcher.
+    pub fn shutdown_if_idle(&mut self) -> bool {
+        if self.is_running() && self.last_used.elapsed() >= self.idle_timeout {
+            self.stop();
+            return true;
+        }
+
+        false
+    }
+
+    /// Closes the daemon's stdin so it can exit on its own, killing it if it doesn't.
+    pub fn stop(&mut self) {
+        self.cancelled.clear();
+
+        if let Some(process) = self.process.take() {
+            let DaemonProcess {
+                mut child, stdin, ..
+            } = process;
+
+            drop(stdin);
+            let _ = wait_with_deadline(&mut child, SHUTDOWN_GRACE, &CancelToken::new());
+        }
+    }
+
+    fn write(&mut self, frame: &DaemonMessage) -> Result<(), RunnerError> {
+        let encoding = self.runner.encoding;
+        let process = self.ensure_running()?;
+
+        write_frame(&mut process.stdin, encoding, frame).map_err(|e| {
+            RunnerError::Io(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                e.to_string(),
+            ))
+        })
+    }
+
+    /// Tells a running daemon to skip a request. One that already exited never
+    /// saw it, so it isn't started again just for this.
+    fn send_cancel(&mut self, id: u64) {
+        let encoding = self.runner.encoding;
+
+        if let Some(process) = &mut self.process {
+            if matches!(process.child.try_wait(), Ok(None)) {
+                let _ = write_frame(&mut process.stdin, encoding, &DaemonMessage::Cancel(id));
+                self.cancelled.push((id, Instant::now() + CANCEL_GRACE));
+            }
+        }
+    }
+
+    /// Waits for cancelled requests to end, restarting the daemon if one of them
+    /// is past its grace deadline
+    fn finish_cancelled(&mut self) {
+        while let Some(deadline) = self.cancelled.iter().map(|(_, deadline)| *deadline).min() {
+            let Some(process) = &mut self.process else {
+                self.cancelled.clear();
+                return;
+            };
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            match process.responses.recv_timeout(remaining) {
+                Ok(Ok(DaemonResponse {
+                    id,
+                    frame: StreamFrame::End(_),
+                })) => self
+                    .cancelled
+                    .retain(|(cancelled_id, _)| *cancelled_id != id),
+                Ok(Ok(_)) => {}
+                // Past the deadline, broken stream or exited daemon
+                Ok(Err(_)) | Err(_) => {
+                    let _ = process.child.kill();
+                    self.stop();
+                }
+            }
+        }
+    }
+
+    fn ensure_running(&mut self) -> Result<&mut DaemonProcess, RunnerError> {
+        let running = match &mut self.process {
+            Some(process) => matches!(process.child.try_wait(), Ok(None)),
+            None => false,
+        };
+
+        if !running {
+            self.stop();
+            self.process = Some(self.spawn()?);
+        }
+
+        Ok(self.process.as_mut().expect("daemon was just started"))
+    }
+
+    fn spawn(&self) -> Result<DaemonProcess, RunnerError> {
+        let mut child = self
+            .runner
+            .command()?
+            .env(DAEMON_ENV, "1")
+            .spawn()
+            .map_err(RunnerError::Spawn)?;
+
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        let encoding = self.runner.encoding;
+        let max_frame_bytes = self.runner.limits.max_frame_bytes;
+        let mut stderr_pipe = child.stderr.take().expect("stderr is piped");
+
+        let (sender, responses) = mpsc::channel();
+
+        thread::spawn(move || loop {
+            let response = match read_frame(&mut stdout, encoding, max_frame_bytes) {
+                Ok(Some(response)) => Ok(response),
+                Ok(None) => break,
+                Err(error) => Err(error.to_string()),
+            };
+
+            let last = response.is_err();
+
+            if sender.send(response).is_err() || last {
+                break;
+            }
+        });
+
+        let stderr = Arc::new(Mutex::new(vec![]));
+        let stderr_writer = Arc::clone(&stderr);
+
+        thread::spawn(move || {
+            let mut buffer = [0u8; 1024];
+
+            while let Ok(read) = stderr_pipe.read(&mut buffer) {
+                if read == 0 {
+                    break;
+                }
+
+                let mut stderr = stderr_writer.lock().unwrap();
+                stderr.extend_from_slice(&buffer[..read]);
+
+                if stderr.len() > MAX_STDERR_BYTES {
+                    let overflow = stderr.len() - MAX_STDERR_BYTES;
+                    stderr.drain(..overflow);
+                }
+            }
+        });
+
+        Ok(DaemonProcess {
+            child,
+            stdin,
+            responses,
+            stderr,
+        })
+    }
+
+    fn wait_for(
+        &mut self,
+        id: u64,
+        cancel: &CancelToken,
+        on_batch: &mut dyn FnMut(&[SearchResult]),
+    ) -> Result<ExtensionResponse, RunnerError> {
+        let deadline = Instant::now() + self.runner.timeout;
+        let mut validator = ResultValidator::new(&self.runner.limits);
+        let mut partial_results = vec![];
+
+        loop {
+            if cancel.is_cancelled() {
+                // The daemon keeps running, it only has to skip the request
+                self.send_cancel(id);
+                return Err(RunnerError::Cancelled);
+            }
+
+            let process = self.process.as_mut().expect("daemon is running");
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            match process.responses.recv_timeout(remaining.min(POLL_INTERVAL)) {
+                Ok(Ok(DaemonResponse {
+                    id: response_id,
+                    frame,
+                })) if response_id == id => match frame {
+                    StreamFrame::Batch(batch) => {
+                        let batch = validator.filter(batch);
+
+                        if !batch.is_empty() {
+                            on_batch(&batch);
+                        }
+
+                        partial_results.extend(batch);
+                    }
+                    StreamFrame::End(response) => {
+                        let response = validator.filter_response(response);
+                        self.runner.report(validator.into_report());
+
+                        if !response.get_results().is_empty() {
+                            on_batch(response.get_results());
+                        }
+
+                        return Ok(response.merge_partial_results(partial_results));
+                    }
+                },
+                // A late reply to a request we already gave up on
+                Ok(Ok(DaemonResponse {
+                    id: response_id,
+                    frame: StreamFrame::End(_),
+                })) => self.cancelled.retain(|(id, _)| *id != response_id),
+                Ok(Ok(_)) => {}
+                // The stream can't be trusted after a bad frame, so start over next time
+                Ok(Err(error)) => {
+                    let stderr = process.stderr();
+                    let _ = process.child.kill();
+                    self.stop();
+
+                    return Err(RunnerError::InvalidOutput { error, stderr });
+                }
+                Err(RecvTimeoutError::Timeout) if !remaining.is_zero() => {}
+                Err(RecvTimeoutError::Timeout) => {
+                    let stderr = process.stderr();
+                    let _ = process.child.kill();
+                    self.stop();
+
+                    return Err(RunnerError::TimedOut {
+                        timeout: self.runner.timeout,
+                        stderr,
+                    });
+                }
+                // Stdout was closed, which a daemon that keeps running can't recover from
+                Err(RecvTimeoutError::Disconnected) => {
+                    let status =
+                        wait_with_deadline(&mut process.child, SHUTDOWN_GRACE, &CancelToken::new());
+                    let stderr = process.stderr();
+                    self.process = None;
+
+                    return Err(match status.map_err(RunnerError::Io)? {
+                        Some(status) if !status.success() => {
+                            RunnerError::Crashed { status, stderr }
+                        }
+                        Some(_) => RunnerError::InvalidOutput {
+                            error: "Daemon exited without replying".to_string(),
+                            stderr,
+                        },
+                        None => RunnerError::InvalidOutput {
+                            error: "Daemon closed its output".to_string(),
+                            stderr,
+                        },
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl DaemonProcess {
+    fn stderr(&self) -> String {
+        let stderr = self.stderr.lock().unwrap();
+        String::from_utf8_lossy(&stderr).trim().to_owned()
+    }
+}
+
+impl Drop for ExtensionDaemon {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
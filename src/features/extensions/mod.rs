@@ -1,6 +1,14 @@
-use std::{error::Error, fs, path::PathBuf};
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
 
-use super::paths::get_extensions_dir;
+use super::{
+    paths::{check_extension_id, get_extensions_dir},
+    protocol::{check_protocol_version, Encoding, ProtocolMismatch},
+    sandbox::SandboxPolicy,
+};
 use serde::{Deserialize, Serialize};
 use walkdir::WalkDir;
 
@@ -22,6 +30,46 @@ pub struct Extension {
     pub repository_link: Option<String>,
 
     pub settings: Vec<Setting>,
+
+    #[serde(default = "default_binary")]
+    pub binary: String,
+
+    /// Keeps the extension running between requests instead of starting it for each one
+    #[serde(default = "default_false")]
+    pub daemon: bool,
+
+    /// Sends results in batches as they are found. See `send_partial_results`
+    #[serde(default = "default_false")]
+    pub streaming: bool,
+
+    /// Protocol version of tigris-core the extension was built with
+    #[serde(default = "default_protocol_version")]
+    pub protocol_version: u32,
+
+    /// Encoding of requests and responses, `postcard` or `json`
+    #[serde(default)]
+    pub encoding: Encoding,
+
+    /// Restrictions the host applies when running the extension
+    #[serde(default)]
+    pub sandbox: Option<SandboxPolicy>,
+
+    /// Background work the host runs outside of searches
+    #[serde(default)]
+    pub jobs: Vec<Job>,
+
+    /// Actions other extensions can run with a `RunExtension` action
+    #[serde(default)]
+    pub public_actions: Vec<PublicAction>,
+
+    /// Lets the host reuse results instead of running the extension for each query
+    #[serde(default)]
+    pub cache: Option<CachePolicy>,
+
+    /// Queries starting with this keyword only go to this extension. Extensions
+    /// without one get every query that has no keyword of its own
+    #[serde(default)]
+    pub keyword: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -72,10 +120,69 @@ pub struct ConditionalShow {
     pub setting_value: String,
 }
 
+/// Job run by the host with `RequestType::RunJob`, periodically and/or when
+/// the launcher starts
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Job {
+    pub id: String,
+
+    /// Seconds between runs. Jobs without it only run on startup
+    #[serde(default)]
+    pub interval_secs: Option<u64>,
+
+    #[serde(default = "default_false")]
+    pub on_startup: bool,
+
+    /// Seconds the job can run before it's killed
+    #[serde(default = "default_job_timeout")]
+    pub timeout_secs: u64,
+}
+
+/// Action of an extension that can be called by other extensions
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PublicAction {
+    pub action: String,
+
+    /// Ids of the extensions allowed to call it. Any extension can if it's empty
+    #[serde(default)]
+    pub allowed_callers: Vec<String>,
+}
+
+/// How the host caches the results of an extension, by query text
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct CachePolicy {
+    /// Seconds results are reused for. Responses can override it with `CachedResults`
+    pub ttl_secs: u64,
+
+    /// Keeps results on disk so they survive a launcher restart
+    #[serde(default = "default_false")]
+    pub persist: bool,
+
+    /// Drops cached results when an action or form of the extension runs
+    #[serde(default = "default_false")]
+    pub invalidate_on_action: bool,
+}
+
 fn default_none_string() -> Option<String> {
     None
 }
 
+fn default_binary() -> String {
+    String::from("extension")
+}
+
+fn default_false() -> bool {
+    false
+}
+
+fn default_protocol_version() -> u32 {
+    1
+}
+
+fn default_job_timeout() -> u64 {
+    60
+}
+
 fn default_none_usize() -> Option<usize> {
     None
 }
@@ -92,22 +199,88 @@ fn default_conditional_show() -> Option<Vec<ConditionalShow>> {
 // ==== Methods
 // =================================================================
 
-pub fn get_extension_dir(extension_id: &str) -> Result<PathBuf, Box<dyn Error>> {
-    for entry in WalkDir::new(get_extensions_dir()?) {
-        if let Ok(entry) = entry {
-            let name = entry.file_name();
-
-            if name == "manifest.json" {
-                if let Ok(content) = fs::read_to_string(entry.path()) {
-                    if let Ok(extension) = serde_json::from_str::<Extension>(&content) {
-                        if extension.id == extension_id {
-                            return Ok(entry.path().parent().unwrap().to_owned());
-                        }
+impl Extension {
+    pub fn check_protocol_version(&self) -> Result<(), ProtocolMismatch> {
+        check_protocol_version(self.protocol_version)
+    }
+
+    /// Checks that `caller_id` can run `action` of this extension. Extensions can
+    /// always run their own actions.
+    pub fn check_action_caller(&self, action: &str, caller_id: &str) -> Result<(), String> {
+        check_action_caller(&self.id, &self.public_actions, action, caller_id)
+    }
+}
+
+pub(crate) fn check_action_caller(
+    extension_id: &str,
+    public_actions: &[PublicAction],
+    action: &str,
+    caller_id: &str,
+) -> Result<(), String> {
+    if caller_id == extension_id {
+        return Ok(());
+    }
+
+    let public_action = public_actions
+        .iter()
+        .find(|a| a.action == action)
+        .ok_or_else(|| format!("Action {action} of {extension_id} is not public"))?;
+
+    if !public_action.allowed_callers.is_empty()
+        && !public_action.allowed_callers.iter().any(|c| c == caller_id)
+    {
+        return Err(format!(
+            "{caller_id} is not allowed to run action {action} of {extension_id}"
+        ));
+    }
+
+    Ok(())
+}
+
+fn read_manifests() -> Result<Vec<(PathBuf, Extension)>, Box<dyn Error>> {
+    let mut manifests = vec![];
+
+    for entry in WalkDir::new(get_extensions_dir()?).into_iter().flatten() {
+        if entry.file_name() == "manifest.json" {
+            if let Ok(content) = fs::read_to_string(entry.path()) {
+                if let Ok(extension) = serde_json::from_str::<Extension>(&content) {
+                    let dir = entry.path().parent().unwrap();
+
+                    // The id names the extension's data dir, so it has to be safe
+                    // to join and can't take over the id of another extension
+                    if check_extension_id(&extension.id).is_ok()
+                        && dir
+                            .file_name()
+                            .is_some_and(|name| name == extension.id.as_str())
+                    {
+                        manifests.push((dir.to_owned(), extension));
                     }
                 }
             }
         }
     }
 
-    Err("Could not find any extension with the given id".into())
+    Ok(manifests)
+}
+
+pub fn get_extension_manifest(extension_dir: &Path) -> Result<Extension, Box<dyn Error>> {
+    let content = fs::read_to_string(extension_dir.join("manifest.json"))?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+pub fn get_extension_dir(extension_id: &str) -> Result<PathBuf, Box<dyn Error>> {
+    read_manifests()?
+        .into_iter()
+        .find(|(_, extension)| extension.id == extension_id)
+        .map(|(dir, _)| dir)
+        .ok_or_else(|| "Could not find any extension with the given id".into())
+}
+
+/// Returns the manifests of every installed extension. Invalid manifests are skipped.
+#[cfg(feature = "host")]
+pub fn get_extensions() -> Result<Vec<Extension>, Box<dyn Error>> {
+    Ok(read_manifests()?
+        .into_iter()
+        .map(|(_, extension)| extension)
+        .collect())
 }
@@ -0,0 +1,222 @@
+//! Hands forms between the launcher and the window that shows them. Each form
+//! gets its own key and files in the runtime dir, only readable by the user and
+//! replaced atomically so readers never see half a form.
+
+use std::{
+    error::Error,
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use postcard::{from_bytes, to_allocvec};
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{
+    actions::OpenFormAction,
+    api::FormResultsRequest,
+    paths::{get_form_file_path, get_form_results_path, get_forms_dir},
+};
+
+/// Keeps keys and temporary files unique within the process
+static NEXT_FILE: AtomicU64 = AtomicU64::new(0);
+
+// =================================================================
+// ==== Methods
+// =================================================================
+
+/// Stores a form to be shown and returns the key to read it back with
+pub fn write_form(action: &OpenFormAction) -> Result<String, Box<dyn Error>> {
+    let form_key = get_unique_name();
+    write_atomically(&get_form_file_path(&form_key)?, action)?;
+
+    Ok(form_key)
+}
+
+pub fn read_form(form_key: &str) -> Result<OpenFormAction, Box<dyn Error>> {
+    check_form_key(form_key)?;
+    read_file(&get_form_file_path(form_key)?)
+}
+
+/// Stores the values submitted in a form
+pub fn write_form_results(
+    form_key: &str,
+    results: &FormResultsRequest,
+) -> Result<(), Box<dyn Error>> {
+    check_form_key(form_key)?;
+    write_atomically(&get_form_results_path(form_key)?, results)
+}
+
+/// Reads the values submitted in a form and removes its files
+pub fn take_form_results(form_key: &str) -> Result<FormResultsRequest, Box<dyn Error>> {
+    check_form_key(form_key)?;
+
+    let results = read_file(&get_form_results_path(form_key)?)?;
+    remove_form(form_key)?;
+
+    Ok(results)
+}
+
+/// Removes the files of a form, e.g. when it's closed without submitting
+pub fn remove_form(form_key: &str) -> Result<(), Box<dyn Error>> {
+    check_form_key(form_key)?;
+
+    for path in [
+        get_form_file_path(form_key)?,
+        get_form_results_path(form_key)?,
+    ] {
+        match fs::remove_file(path) {
+            Err(error) if error.kind() != ErrorKind::NotFound => return Err(error.into()),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes files older than `max_age`, left behind by forms that were never
+/// closed. Returns how many were removed.
+pub fn remove_stale_forms(max_age: Duration) -> Result<usize, Box<dyn Error>> {
+    let mut removed = 0;
+
+    for entry in fs::read_dir(get_forms_dir()?)?.flatten() {
+        let modified = entry.metadata().and_then(|m| m.modified());
+
+        if modified.is_ok_and(|m| m.elapsed().is_ok_and(|age| age > max_age))
+            && fs::remove_file(entry.path()).is_ok()
+        {
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Keys end up in file names, so they can't point outside the forms dir
+fn check_form_key(form_key: &str) -> Result<(), Box<dyn Error>> {
+    if form_key.is_empty()
+        || !form_key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        return Err(format!("Invalid form key {form_key:?}").into());
+    }
+
+    Ok(())
+}
+
+fn get_unique_name() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_nanos())
+        .unwrap_or_default();
+
+    format!(
+        "{}-{nanos}-{}",
+        std::process::id(),
+        NEXT_FILE.fetch_add(1, Ordering::SeqCst)
+    )
+}
+
+/// Writes to a new 0600 file next to `path` and renames it over `path`
+fn write_atomically<T: Serialize>(path: &Path, value: &T) -> Result<(), Box<dyn Error>> {
+    let bytes = to_allocvec(value)?;
+    let tmp_path = path.with_extension(format!("{}.tmp", get_unique_name()));
+
+    let result = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(&bytes)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp_path, path));
+
+    if let Err(error) = result {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(error.into());
+    }
+
+    Ok(())
+}
+
+fn read_file<T: DeserializeOwned>(path: &Path) -> Result<T, Box<dyn Error>> {
+    let bytes = fs::read(path)?;
+    Ok(from_bytes(&bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::fs::PermissionsExt, path::PathBuf};
+
+    use super::*;
+
+    /// Throwaway dir, so the tests don't touch the user's runtime dir
+    fn get_test_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tigris-forms-{}", get_unique_name()));
+        fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    fn get_file_names(dir: &Path) -> Vec<String> {
+        fs::read_dir(dir)
+            .unwrap()
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn keys_that_could_leave_the_forms_dir_are_rejected() {
+        for key in [
+            "",
+            ".",
+            "..",
+            "../settings",
+            "a/b",
+            "/tmp/x",
+            "a.form",
+            "a b",
+        ] {
+            assert!(check_form_key(key).is_err(), "{key:?}");
+            assert!(read_form(key).is_err(), "{key:?}");
+            assert!(take_form_results(key).is_err(), "{key:?}");
+            assert!(remove_form(key).is_err(), "{key:?}");
+        }
+
+        assert!(check_form_key(&get_unique_name()).is_ok());
+    }
+
+    #[test]
+    fn files_are_only_readable_by_the_user() {
+        let dir = get_test_dir();
+        let path = dir.join("key.form");
+
+        write_atomically(&path, &"form".to_string()).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn writes_replace_the_file_without_leaving_temporary_files() {
+        let dir = get_test_dir();
+        let path = dir.join("key.results");
+
+        write_atomically(&path, &"first".to_string()).unwrap();
+        write_atomically(&path, &"second".to_string()).unwrap();
+
+        let value: String = read_file(&path).unwrap();
+        let names = get_file_names(&dir);
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(value, "second");
+        assert_eq!(names, ["key.results"]);
+    }
+}
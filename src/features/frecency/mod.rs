@@ -0,0 +1,339 @@
+use std::{
+    cmp::Ordering,
+    error::Error,
+    fs,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use postcard::{from_bytes, to_allocvec};
+use serde::{Deserialize, Serialize};
+
+use super::{paths::get_frecency_path, search_results::SearchResult};
+
+/// Time after which a selection counts half as much
+pub const DEFAULT_HALF_LIFE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+pub const DEFAULT_MAX_ENTRIES: usize = 2000;
+
+/// Selections made with a query that starts with the current one, e.g. picking
+/// Firefox after typing `fir` boosts it for `fi`
+const PREFIX_WEIGHT: f64 = 0.5;
+
+/// Selections made with any other query
+const OTHER_QUERY_WEIGHT: f64 = 0.1;
+
+/// Remembers which results the user picks for which queries, so the launcher
+/// can move them up. Scores decay over time, so recent picks count more than
+/// old ones.
+#[derive(Debug, Clone)]
+pub struct FrecencyStore {
+    entries: Vec<FrecencyEntry>,
+    half_life: Duration,
+    max_entries: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct FrecencyEntry {
+    query: String,
+    result_id: String,
+    /// Score as of `last_used`, each selection adds 1
+    score: f64,
+    /// Unix time in seconds
+    last_used: u64,
+}
+
+// =================================================================
+// ==== Methods
+// =================================================================
+
+impl FrecencyStore {
+    pub fn new() -> Self {
+        Self {
+            entries: vec![],
+            half_life: DEFAULT_HALF_LIFE,
+            max_entries: DEFAULT_MAX_ENTRIES,
+        }
+    }
+
+    /// Store with the selections saved by [`FrecencyStore::save`]. Starts empty
+    /// if there's nothing saved or it can't be read.
+    pub fn load() -> Self {
+        Self {
+            entries: read_entries().unwrap_or_default(),
+            ..Self::new()
+        }
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        fs::write(get_frecency_path()?, to_allocvec(&self.entries)?)?;
+        Ok(())
+    }
+
+    pub fn set_half_life(mut self, half_life: Duration) -> Self {
+        self.half_life = half_life;
+        self
+    }
+
+    pub fn set_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// Records that the user picked a result after typing `query`. When the
+    /// store is full, the entry with the lowest score is forgotten.
+    pub fn record(&mut self, query: &str, result_id: &str) {
+        self.record_at(query, result_id, get_unix_time());
+    }
+
+    /// [`FrecencyStore::record`] at `now`, in Unix seconds
+    fn record_at(&mut self, query: &str, result_id: &str, now: u64) {
+        let query = normalize_query(query);
+
+        match self
+            .entries
+            .iter_mut()
+            .find(|e| e.query == query && e.result_id == result_id)
+        {
+            Some(entry) => {
+                entry.score = get_decayed_score(entry, now, self.half_life) + 1.0;
+                entry.last_used = now;
+            }
+            None => {
+                self.entries.push(FrecencyEntry {
+                    query,
+                    result_id: result_id.to_owned(),
+                    score: 1.0,
+                    last_used: now,
+                });
+            }
+        }
+
+        while self.entries.len() > self.max_entries {
+            let lowest = self
+                .entries
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| {
+                    get_decayed_score(a, now, self.half_life).total_cmp(&get_decayed_score(
+                        b,
+                        now,
+                        self.half_life,
+                    ))
+                })
+                .map(|(index, _)| index);
+
+            match lowest {
+                Some(index) => self.entries.remove(index),
+                None => break,
+            };
+        }
+    }
+
+    /// How much the user favors the result for this query. 0 if it was never
+    /// picked, about 1 for each recent pick with the same query.
+    pub fn get_boost(&self, query: &str, result_id: &str) -> f64 {
+        self.get_boost_at(query, result_id, get_unix_time())
+    }
+
+    /// [`FrecencyStore::get_boost`] at `now`, in Unix seconds
+    fn get_boost_at(&self, query: &str, result_id: &str, now: u64) -> f64 {
+        let query = normalize_query(query);
+
+        self.entries
+            .iter()
+            .filter(|entry| entry.result_id == result_id)
+            .map(|entry| {
+                let weight = if entry.query == query {
+                    1.0
+                } else if !query.is_empty() && entry.query.starts_with(&query) {
+                    PREFIX_WEIGHT
+                } else {
+                    OTHER_QUERY_WEIGHT
+                };
+
+                get_decayed_score(entry, now, self.half_life) * weight
+            })
+            .sum()
+    }
+
+    /// Moves the results the user picked before to the top, most favored first.
+    /// The others keep their order. `source` is the one given to [`get_result_id`].
+    pub fn sort_results(
+        &self,
+        query: &str,
+        source: &str,
+        results: Vec<SearchResult>,
+    ) -> Vec<SearchResult> {
+        let mut boosted: Vec<(f64, SearchResult)> = results
+            .into_iter()
+            .map(|result| {
+                (
+                    self.get_boost(query, &get_result_id(source, &result)),
+                    result,
+                )
+            })
+            .collect();
+
+        // Boosts are never NaN, and `partial_cmp` keeps -0.0 and 0.0 equal
+        boosted.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+        boosted.into_iter().map(|(_, result)| result).collect()
+    }
+
+    /// Forgets every selection of a result, e.g. after an app is uninstalled
+    pub fn forget(&mut self, result_id: &str) {
+        self.entries.retain(|entry| entry.result_id != result_id);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+impl Default for FrecencyStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Id of a result for the store. `source` tells apart results with the same
+/// title, e.g. `apps` or the id of the extension that returned it.
+pub fn get_result_id(source: &str, result: &SearchResult) -> String {
+    format!("{source}/{}", result.title)
+}
+
+fn normalize_query(query: &str) -> String {
+    query.trim().to_lowercase()
+}
+
+fn get_decayed_score(entry: &FrecencyEntry, now: u64, half_life: Duration) -> f64 {
+    let elapsed = now.saturating_sub(entry.last_used) as f64;
+    let half_lives = elapsed / half_life.as_secs_f64().max(1.0);
+
+    entry.score * 0.5f64.powf(half_lives)
+}
+
+fn get_unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default()
+}
+
+fn read_entries() -> Result<Vec<FrecencyEntry>, Box<dyn Error>> {
+    let bytes = fs::read(get_frecency_path()?)?;
+    Ok(from_bytes(&bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+    const DAY: u64 = 24 * 60 * 60;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn scores_halve_every_half_life() {
+        let mut store = FrecencyStore::new();
+        store.record_at("fire", "apps/Firefox", NOW);
+
+        assert_close(store.get_boost_at("fire", "apps/Firefox", NOW), 1.0);
+        assert_close(
+            store.get_boost_at("fire", "apps/Firefox", NOW + 7 * DAY),
+            0.5,
+        );
+        assert_close(
+            store.get_boost_at("fire", "apps/Firefox", NOW + 14 * DAY),
+            0.25,
+        );
+        assert_close(
+            store.get_boost_at("fire", "apps/Firefox", NOW + 21 * DAY / 2),
+            0.5f64.powf(1.5),
+        );
+
+        let store = store.set_half_life(Duration::from_secs(DAY));
+        assert_close(
+            store.get_boost_at("fire", "apps/Firefox", NOW + 2 * DAY),
+            0.25,
+        );
+    }
+
+    #[test]
+    fn new_picks_add_to_the_decayed_score() {
+        let mut store = FrecencyStore::new();
+        store.record_at("fire", "apps/Firefox", NOW);
+        store.record_at("fire", "apps/Firefox", NOW + 7 * DAY);
+
+        assert_close(
+            store.get_boost_at("fire", "apps/Firefox", NOW + 7 * DAY),
+            1.5,
+        );
+        assert_close(
+            store.get_boost_at("fire", "apps/Firefox", NOW + 14 * DAY),
+            0.75,
+        );
+    }
+
+    #[test]
+    fn other_queries_count_less() {
+        let mut store = FrecencyStore::new();
+        store.record_at("fire", "apps/Firefox", NOW);
+
+        assert_close(store.get_boost_at(" FIRE ", "apps/Firefox", NOW), 1.0);
+        assert_close(store.get_boost_at("fi", "apps/Firefox", NOW), PREFIX_WEIGHT);
+        assert_close(
+            store.get_boost_at("firefox", "apps/Firefox", NOW),
+            OTHER_QUERY_WEIGHT,
+        );
+        assert_close(
+            store.get_boost_at("", "apps/Firefox", NOW),
+            OTHER_QUERY_WEIGHT,
+        );
+        assert_close(store.get_boost_at("fire", "apps/Files", NOW), 0.0);
+
+        store.record_at("fi", "apps/Firefox", NOW);
+        assert_close(
+            store.get_boost_at("fi", "apps/Firefox", NOW),
+            1.0 + PREFIX_WEIGHT,
+        );
+    }
+
+    #[test]
+    fn the_lowest_decayed_score_is_evicted_when_full() {
+        let mut store = FrecencyStore::new().set_max_entries(2);
+
+        // Picked twice but long ago, so it ends up below a single recent pick
+        store.record_at("a", "old", NOW - 28 * DAY);
+        store.record_at("a", "old", NOW - 28 * DAY);
+        store.record_at("a", "recent", NOW - DAY);
+        store.record_at("a", "new", NOW);
+
+        assert_eq!(store.entries.len(), 2);
+        assert_close(store.get_boost_at("a", "old", NOW), 0.0);
+        assert!(store.get_boost_at("a", "recent", NOW) > 0.0);
+        assert_close(store.get_boost_at("a", "new", NOW), 1.0);
+    }
+
+    #[test]
+    fn picked_results_move_to_the_top() {
+        let mut store = FrecencyStore::new();
+        let results = vec![
+            SearchResult::new("Files"),
+            SearchResult::new("Firefox"),
+            SearchResult::new("Fish"),
+        ];
+
+        store.record("fi", &get_result_id("apps", &results[2]));
+
+        let titles: Vec<String> = store
+            .sort_results("fi", "apps", results)
+            .into_iter()
+            .map(|result| result.title)
+            .collect();
+
+        assert_eq!(titles, ["Fish", "Files", "Firefox"]);
+    }
+}
@@ -0,0 +1,331 @@
+use std::{
+    error::Error,
+    fs,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use postcard::{from_bytes, to_allocvec};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    api::{ExtensionRequest, ExtensionResponse},
+    extensions::{get_extensions, Extension, Job},
+    paths::get_jobs_status_path,
+    runner::ExtensionRunner,
+};
+
+/// Wait before the first retry of a failed job, doubled on each failure
+pub const BASE_BACKOFF: Duration = Duration::from_secs(30);
+
+pub const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+/// Outcome of the last run of a job, kept between launcher restarts
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct JobStatus {
+    pub extension_id: String,
+    pub job_id: String,
+    /// Unix time in seconds
+    pub last_run: Option<u64>,
+    /// Error of the last run, `None` if it succeeded
+    pub last_error: Option<String>,
+    /// Failed runs in a row
+    pub failures: u32,
+}
+
+/// Runs the jobs declared by extensions when they are due. It doesn't start
+/// threads of its own: the launcher calls [`JobScheduler::run_due_jobs`]
+/// periodically, e.g. from a background thread sleeping for [`JobScheduler::get_next_run`].
+pub struct JobScheduler {
+    jobs: Vec<ScheduledJob>,
+}
+
+struct ScheduledJob {
+    job: Job,
+    status: JobStatus,
+    next_run: Option<Instant>,
+}
+
+// =================================================================
+// ==== Methods
+// =================================================================
+
+impl JobScheduler {
+    pub fn new(extensions: &[Extension]) -> Self {
+        let saved_statuses = read_statuses().unwrap_or_default();
+        let now = Instant::now();
+        let mut jobs = vec![];
+
+        for extension in extensions {
+            for job in &extension.jobs {
+                let status = saved_statuses
+                    .iter()
+                    .find(|s| s.extension_id == extension.id && s.job_id == job.id)
+                    .cloned()
+                    .unwrap_or_else(|| JobStatus::new(&extension.id, &job.id));
+
+                let next_run = get_first_run(job, &status, now, get_unix_time());
+
+                jobs.push(ScheduledJob {
+                    job: job.to_owned(),
+                    status,
+                    next_run,
+                });
+            }
+        }
+
+        Self { jobs }
+    }
+
+    /// Scheduler for the jobs of all installed extensions
+    pub fn from_installed() -> Result<Self, Box<dyn Error>> {
+        Ok(Self::new(&get_extensions()?))
+    }
+
+    pub fn get_statuses(&self) -> Vec<JobStatus> {
+        self.jobs.iter().map(|job| job.status.to_owned()).collect()
+    }
+
+    /// Time until the next job is due, `None` if nothing is scheduled
+    pub fn get_next_run(&self) -> Option<Duration> {
+        let now = Instant::now();
+
+        self.jobs
+            .iter()
+            .filter_map(|job| job.next_run)
+            .min()
+            .map(|next_run| next_run.saturating_duration_since(now))
+    }
+
+    /// Runs every job that is due, one after the other, and saves their status.
+    /// Returns the status of the jobs that ran, or the error saving it. The jobs
+    /// are rescheduled either way.
+    pub fn run_due_jobs(&mut self) -> Result<Vec<JobStatus>, Box<dyn Error>> {
+        let now = Instant::now();
+        let mut ran = vec![];
+
+        for job in &mut self.jobs {
+            if job.next_run.is_some_and(|next_run| next_run <= now) {
+                job.run();
+                ran.push(job.status.to_owned());
+            }
+        }
+
+        if !ran.is_empty() {
+            self.save()?;
+        }
+
+        Ok(ran)
+    }
+
+    /// Runs a job right away, whether it's due or not
+    pub fn run_job(&mut self, extension_id: &str, job_id: &str) -> Result<JobStatus, String> {
+        let job = self
+            .jobs
+            .iter_mut()
+            .find(|j| j.status.extension_id == extension_id && j.job.id == job_id)
+            .ok_or_else(|| format!("No job {job_id} in extension {extension_id}"))?;
+
+        job.run();
+        let status = job.status.to_owned();
+        self.save()
+            .map_err(|e| format!("Error saving job status: {e}"))?;
+
+        Ok(status)
+    }
+
+    fn save(&self) -> Result<(), Box<dyn Error>> {
+        write_statuses(&self.get_statuses())
+    }
+}
+
+impl ScheduledJob {
+    fn run(&mut self) {
+        let result = run_extension_job(&self.status.extension_id, &self.job);
+
+        self.status.last_run = Some(get_unix_time());
+
+        match result {
+            Ok(()) => {
+                self.status.last_error = None;
+                self.status.failures = 0;
+                self.next_run = self.get_interval().map(|i| Instant::now() + i);
+            }
+            Err(error) => {
+                self.status.last_error = Some(error);
+                self.status.failures += 1;
+                self.next_run = Some(Instant::now() + get_backoff(self.status.failures));
+            }
+        }
+    }
+
+    fn get_interval(&self) -> Option<Duration> {
+        self.job.interval_secs.map(Duration::from_secs)
+    }
+}
+
+impl JobStatus {
+    pub fn new(extension_id: &str, job_id: &str) -> Self {
+        Self {
+            extension_id: extension_id.to_owned(),
+            job_id: job_id.to_owned(),
+            last_run: None,
+            last_error: None,
+            failures: 0,
+        }
+    }
+
+    pub fn is_failing(&self) -> bool {
+        self.failures > 0
+    }
+}
+
+fn run_extension_job(extension_id: &str, job: &Job) -> Result<(), String> {
+    let runner = ExtensionRunner::new(extension_id)
+        .map_err(|e| e.to_string())?
+        .set_timeout(Duration::from_secs(job.timeout_secs));
+
+    match runner.send(&ExtensionRequest::new_run_job_request(&job.id)) {
+        Ok(ExtensionResponse::Error { message, .. }) => Err(message),
+        Ok(_) => Ok(()),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+/// Startup jobs and jobs that never ran are due right away. Periodic jobs
+/// continue from their last run, failing ones from their backoff. `unix_time`
+/// is the current time in seconds, to compare with the saved last run.
+fn get_first_run(job: &Job, status: &JobStatus, now: Instant, unix_time: u64) -> Option<Instant> {
+    if job.on_startup {
+        return Some(now);
+    }
+
+    let interval = if status.is_failing() {
+        get_backoff(status.failures)
+    } else {
+        Duration::from_secs(job.interval_secs?)
+    };
+
+    let Some(last_run) = status.last_run else {
+        return Some(now);
+    };
+
+    let elapsed = Duration::from_secs(unix_time.saturating_sub(last_run));
+    Some(now + interval.saturating_sub(elapsed))
+}
+
+fn get_backoff(failures: u32) -> Duration {
+    let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+    BASE_BACKOFF.saturating_mul(factor).min(MAX_BACKOFF)
+}
+
+fn get_unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default()
+}
+
+fn read_statuses() -> Result<Vec<JobStatus>, Box<dyn Error>> {
+    let bytes = fs::read(get_jobs_status_path()?)?;
+    Ok(from_bytes(&bytes)?)
+}
+
+fn write_statuses(statuses: &Vec<JobStatus>) -> Result<(), Box<dyn Error>> {
+    let bytes = to_allocvec(statuses)?;
+    Ok(fs::write(get_jobs_status_path()?, bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW_UNIX: u64 = 1_700_000_000;
+
+    fn get_job(interval_secs: Option<u64>, on_startup: bool) -> Job {
+        Job {
+            id: "job".to_string(),
+            interval_secs,
+            on_startup,
+            timeout_secs: 60,
+        }
+    }
+
+    fn get_status(last_run: Option<u64>, failures: u32) -> JobStatus {
+        JobStatus {
+            last_run,
+            last_error: (failures > 0).then(|| "failed".to_string()),
+            failures,
+            ..JobStatus::new("extension", "job")
+        }
+    }
+
+    fn get_delay(job: &Job, status: &JobStatus) -> Option<Duration> {
+        let now = Instant::now();
+        get_first_run(job, status, now, NOW_UNIX).map(|run| run - now)
+    }
+
+    #[test]
+    fn backoff_doubles_from_30_seconds_up_to_an_hour() {
+        let backoffs: Vec<u64> = (1..=9).map(|f| get_backoff(f).as_secs()).collect();
+        assert_eq!(backoffs, [30, 60, 120, 240, 480, 960, 1920, 3600, 3600]);
+
+        assert_eq!(get_backoff(0), BASE_BACKOFF);
+        assert_eq!(get_backoff(u32::MAX), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn startup_jobs_and_jobs_that_never_ran_are_due_right_away() {
+        let ran_recently = get_status(Some(NOW_UNIX - 10), 0);
+        assert_eq!(
+            get_delay(&get_job(Some(3600), true), &ran_recently),
+            Some(Duration::ZERO)
+        );
+
+        let never_ran = get_status(None, 0);
+        assert_eq!(
+            get_delay(&get_job(Some(3600), false), &never_ran),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn jobs_without_an_interval_only_run_on_startup() {
+        assert_eq!(get_delay(&get_job(None, false), &get_status(None, 0)), None);
+    }
+
+    #[test]
+    fn periodic_jobs_continue_from_their_last_run() {
+        let job = get_job(Some(3600), false);
+
+        let ran_recently = get_status(Some(NOW_UNIX - 600), 0);
+        assert_eq!(
+            get_delay(&job, &ran_recently),
+            Some(Duration::from_secs(3000))
+        );
+
+        let overdue = get_status(Some(NOW_UNIX - 7200), 0);
+        assert_eq!(get_delay(&job, &overdue), Some(Duration::ZERO));
+
+        // A clock that went back doesn't delay the job past its interval
+        let in_the_future = get_status(Some(NOW_UNIX + 600), 0);
+        assert_eq!(
+            get_delay(&job, &in_the_future),
+            Some(Duration::from_secs(3600))
+        );
+    }
+
+    #[test]
+    fn failing_jobs_continue_from_their_backoff() {
+        let failed_twice = get_status(Some(NOW_UNIX - 20), 2);
+        assert_eq!(
+            get_delay(&get_job(Some(3600), false), &failed_twice),
+            Some(Duration::from_secs(40))
+        );
+
+        // Jobs without an interval are still retried
+        assert_eq!(
+            get_delay(&get_job(None, false), &failed_twice),
+            Some(Duration::from_secs(40))
+        );
+    }
+}
@@ -0,0 +1,297 @@
+use std::{cmp::Reverse, ops::Range};
+
+use super::search_results::SearchResult;
+
+/// Every matched character
+const SCORE_MATCH: i32 = 16;
+
+/// Match right after the previous one
+const BONUS_CONSECUTIVE: i32 = 8;
+
+/// Match at the start of a word, after a separator or a lower to upper case change
+const BONUS_BOUNDARY: i32 = 10;
+
+/// Match of the first character of the text
+const BONUS_PREFIX: i32 = 14;
+
+/// Each character skipped between two matches
+const PENALTY_GAP: i32 = 2;
+
+/// Each character skipped before the first match, up to `MAX_LEADING_PENALTY`
+const PENALTY_LEADING: i32 = 1;
+
+const MAX_LEADING_PENALTY: i32 = 15;
+
+/// Matches in descriptions count for less than matches in titles
+const DESCRIPTION_WEIGHT: i32 = 2;
+
+/// Only the start of longer texts is matched, so long descriptions stay cheap
+const MAX_TEXT_CHARS: usize = 512;
+
+/// Where a query matched in a text and how well
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuzzyMatch {
+    /// Higher is better. Only comparable between matches of the same query
+    pub score: i32,
+    /// Byte ranges of the matched characters, adjacent ones merged
+    pub ranges: Vec<Range<usize>>,
+}
+
+/// Match of a query against the title and description of a result
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResultMatch {
+    pub score: i32,
+    pub title_ranges: Vec<Range<usize>>,
+    pub description_ranges: Vec<Range<usize>>,
+}
+
+// =================================================================
+// ==== Methods
+// =================================================================
+
+/// Matches the characters of `query` in order in `text`, ignoring case. Picks
+/// the best scoring placement, favoring matches at word starts, at the start of
+/// the text and next to each other. Returns `None` if they aren't all there.
+///
+/// Only the first `MAX_TEXT_CHARS` characters of the text are matched.
+pub fn fuzzy_match(query: &str, text: &str) -> Option<FuzzyMatch> {
+    let query: Vec<char> = query.chars().map(to_lower).collect();
+
+    if query.is_empty() {
+        return Some(FuzzyMatch {
+            score: 0,
+            ranges: vec![],
+        });
+    }
+
+    let text: Vec<(usize, char)> = text.char_indices().take(MAX_TEXT_CHARS).collect();
+
+    let lower: Vec<char> = text.iter().map(|(_, c)| to_lower(*c)).collect();
+
+    if !is_subsequence(&query, &lower) {
+        return None;
+    }
+
+    let bonuses: Vec<i32> = (0..text.len()).map(|j| get_bonus(&text, j)).collect();
+    let (m, n) = (query.len(), text.len());
+
+    // Best score with query[i] matched at text[j], for the current and the
+    // previous query character
+    let mut scores: Vec<Option<i32>> = vec![None; n];
+    let mut previous_scores: Vec<Option<i32>> = vec![None; n];
+
+    // For each query character, `(j, k)` when matching it at text[j] is best
+    // with the previous one at text[k]. Only kept for matches, in order of `j`
+    let mut back_pointers: Vec<Vec<(usize, usize)>> = vec![vec![]; m];
+
+    for (j, c) in lower.iter().enumerate() {
+        if *c == query[0] {
+            let leading = (j as i32 * PENALTY_LEADING).min(MAX_LEADING_PENALTY);
+            scores[j] = Some(SCORE_MATCH + bonuses[j] - leading);
+        }
+    }
+
+    for (i, q) in query.iter().enumerate().skip(1) {
+        std::mem::swap(&mut scores, &mut previous_scores);
+        scores.fill(None);
+
+        // Best earlier match of query[i - 1] that leaves a gap, with its penalty
+        let mut best_gap: Option<(i32, usize)> = None;
+
+        for j in i..n {
+            if j >= 2 {
+                best_gap = best_gap.map(|(score, k)| (score - PENALTY_GAP, k));
+
+                if let Some(score) = previous_scores[j - 2] {
+                    if best_gap.is_none_or(|(best, _)| score - PENALTY_GAP > best) {
+                        best_gap = Some((score - PENALTY_GAP, j - 2));
+                    }
+                }
+            }
+
+            if lower[j] != *q {
+                continue;
+            }
+
+            let consecutive = previous_scores[j - 1].map(|s| (s + BONUS_CONSECUTIVE, j - 1));
+
+            let best = match (consecutive, best_gap) {
+                (Some(c), Some(g)) if g.0 > c.0 => Some(g),
+                (Some(c), _) => Some(c),
+                (None, g) => g,
+            };
+
+            if let Some((score, k)) = best {
+                scores[j] = Some(score + SCORE_MATCH + bonuses[j]);
+                back_pointers[i].push((j, k));
+            }
+        }
+    }
+
+    let (mut j, score) = scores
+        .iter()
+        .enumerate()
+        .filter_map(|(j, score)| score.map(|score| (j, score)))
+        .max_by_key(|(j, score)| (*score, Reverse(*j)))?;
+
+    let mut positions = vec![j; m];
+
+    for i in (1..m).rev() {
+        let index = back_pointers[i]
+            .binary_search_by_key(&j, |(j, _)| *j)
+            .ok()?;
+
+        j = back_pointers[i][index].1;
+        positions[i - 1] = j;
+    }
+
+    Some(FuzzyMatch {
+        score,
+        ranges: get_ranges(&text, &positions),
+    })
+}
+
+/// Matches the query against the title and the description, which counts for less
+pub fn match_result(query: &str, result: &SearchResult) -> Option<ResultMatch> {
+    let title = fuzzy_match(query, &result.title);
+
+    let description = result
+        .description
+        .as_ref()
+        .and_then(|description| fuzzy_match(query, description));
+
+    if title.is_none() && description.is_none() {
+        return None;
+    }
+
+    let title_score = title.as_ref().map(|m| m.score);
+    let description_score = description.as_ref().map(|m| m.score / DESCRIPTION_WEIGHT);
+
+    Some(ResultMatch {
+        score: title_score.max(description_score).unwrap_or_default(),
+        title_ranges: title.map(|m| m.ranges).unwrap_or_default(),
+        description_ranges: description.map(|m| m.ranges).unwrap_or_default(),
+    })
+}
+
+/// Keeps the results that match the query and sorts them best first. Ties keep
+/// their order. An empty query keeps every result as it is.
+pub fn match_results(query: &str, results: Vec<SearchResult>) -> Vec<(SearchResult, ResultMatch)> {
+    let query = query.trim();
+
+    let mut matches: Vec<(SearchResult, ResultMatch)> = results
+        .into_iter()
+        .filter_map(|result| match_result(query, &result).map(|m| (result, m)))
+        .collect();
+
+    matches.sort_by_key(|(_, m)| Reverse(m.score));
+    matches
+}
+
+/// Same as [`match_results`] without the match details
+pub fn filter_results(query: &str, results: Vec<SearchResult>) -> Vec<SearchResult> {
+    match_results(query, results)
+        .into_iter()
+        .map(|(result, _)| result)
+        .collect()
+}
+
+fn to_lower(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+fn is_subsequence(query: &[char], text: &[char]) -> bool {
+    let mut text = text.iter();
+    query.iter().all(|q| text.any(|c| c == q))
+}
+
+fn get_bonus(text: &[(usize, char)], j: usize) -> i32 {
+    if j == 0 {
+        return BONUS_PREFIX;
+    }
+
+    let (previous, current) = (text[j - 1].1, text[j].1);
+
+    if (!previous.is_alphanumeric() && current.is_alphanumeric())
+        || (previous.is_lowercase() && current.is_uppercase())
+    {
+        BONUS_BOUNDARY
+    } else {
+        0
+    }
+}
+
+fn get_ranges(text: &[(usize, char)], positions: &[usize]) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = vec![];
+
+    for &j in positions {
+        let (start, c) = text[j];
+        let end = start + c.len_utf8();
+
+        match ranges.last_mut() {
+            Some(range) if range.end == start => range.end = end,
+            _ => ranges.push(start..end),
+        }
+    }
+
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_query_matches_everything() {
+        let m = fuzzy_match("", "Firefox").unwrap();
+        assert_eq!(m.score, 0);
+        assert!(m.ranges.is_empty());
+
+        let results = vec![SearchResult::new("b"), SearchResult::new("a")];
+        let titles: Vec<String> = filter_results("  ", results)
+            .into_iter()
+            .map(|r| r.title)
+            .collect();
+
+        assert_eq!(titles, ["b", "a"]);
+    }
+
+    #[test]
+    fn missing_characters_dont_match() {
+        assert_eq!(fuzzy_match("fx", "Xf"), None);
+        assert_eq!(fuzzy_match("firefoxx", "Firefox"), None);
+    }
+
+    #[test]
+    fn ranges_are_byte_ranges_on_multibyte_text() {
+        assert_eq!(fuzzy_match("ñé", "Señé").unwrap().ranges, vec![2..6]);
+        assert_eq!(
+            fuzzy_match("ÉA", "Café au lait").unwrap().ranges,
+            vec![3..5, 6..7]
+        );
+    }
+
+    #[test]
+    fn prefers_consecutive_matches_over_gaps() {
+        assert_eq!(
+            fuzzy_match("bar", "xbxaxrxbar").unwrap().ranges,
+            vec![7..10]
+        );
+
+        let consecutive = fuzzy_match("bar", "bar").unwrap().score;
+        let scattered = fuzzy_match("bar", "bxaxr").unwrap().score;
+        assert!(consecutive > scattered);
+    }
+
+    #[test]
+    fn prefers_word_starts() {
+        let m = fuzzy_match("gc", "Google Chrome").unwrap();
+        assert_eq!(m.ranges, vec![0..1, 7..8]);
+    }
+
+    #[test]
+    fn only_matches_the_start_of_long_texts() {
+        let text = format!("{}firefox", "x".repeat(MAX_TEXT_CHARS));
+        assert_eq!(fuzzy_match("firefox", &text), None);
+    }
+}
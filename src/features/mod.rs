@@ -1,23 +1,59 @@
-// General Code
-#[cfg(feature = "default")]
+// Shared Code
+pub mod actions;
+
+pub mod api;
+
+pub mod extensions;
+
+pub mod forms;
+
+pub mod matching;
+
 pub mod paths;
 
-#[cfg(feature = "default")]
-pub mod search_results;
+pub mod protocol;
 
-#[cfg(feature = "default")]
-pub mod actions;
+pub mod sandbox;
 
-#[cfg(feature = "default")]
 pub mod search;
 
-#[cfg(feature = "default")]
-pub mod api;
+pub mod search_results;
 
-#[cfg(feature = "default")]
 pub mod settings;
 
-// Shared Code
-pub mod extensions;
-
 pub mod utils;
+
+// Host Code
+#[cfg(feature = "host")]
+pub mod cache;
+
+#[cfg(feature = "host")]
+pub mod cancellation;
+
+#[cfg(feature = "host")]
+pub mod control;
+
+#[cfg(feature = "host")]
+pub mod daemon;
+
+#[cfg(feature = "host")]
+pub mod frecency;
+
+#[cfg(feature = "host")]
+pub mod jobs;
+
+#[cfg(feature = "host")]
+pub mod router;
+
+#[cfg(feature = "host")]
+pub mod runner;
+
+#[cfg(feature = "host")]
+pub mod validation;
+
+// Extension Code
+#[cfg(feature = "tokio")]
+pub mod async_api;
+
+#[cfg(feature = "testing")]
+pub mod testing;
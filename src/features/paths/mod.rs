@@ -1,11 +1,25 @@
-use std::{error::Error, fs, path::PathBuf};
+use std::{
+    error::Error,
+    fs,
+    os::unix::fs::DirBuilderExt,
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "testing")]
+use std::cell::RefCell;
+
+#[cfg(feature = "testing")]
+thread_local! {
+    /// Set by the test harness so the dirs of the current thread point into a throwaway dir
+    pub(crate) static FAKE_ROOT: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
 
 // =================================================================
 // ==== Directories
 // =================================================================
 
 pub fn get_local_dir() -> Result<PathBuf, Box<dyn Error>> {
-    let dir = dirs::data_dir()
+    let dir = get_base_dir("data", dirs::data_dir())
         .ok_or_else(|| "Error getting local data dir".to_string())?
         .join("org-whiskersapps-tigris");
 
@@ -17,8 +31,8 @@ pub fn get_local_dir() -> Result<PathBuf, Box<dyn Error>> {
 }
 
 pub fn get_config_dir() -> Result<PathBuf, Box<dyn Error>> {
-    let dir = dirs::config_dir()
-        .ok_or_else(|| "Error getting config dir")?
+    let dir = get_base_dir("config", dirs::config_dir())
+        .ok_or("Error getting config dir")?
         .join("org-whiskersapps-tigris");
 
     if !dir.exists() {
@@ -28,8 +42,61 @@ pub fn get_config_dir() -> Result<PathBuf, Box<dyn Error>> {
     Ok(dir)
 }
 
+/// Per-user dir for sockets and other files that don't outlive the session,
+/// only accessible by the user. Without `XDG_RUNTIME_DIR` it's a private dir
+/// in the local dir instead.
+pub fn get_runtime_dir() -> Result<PathBuf, Box<dyn Error>> {
+    let dir = match get_base_dir("runtime", dirs::runtime_dir()) {
+        Some(dir) => dir.join("org-whiskersapps-tigris"),
+        None => get_local_dir()?.join("runtime"),
+    };
+
+    create_private_dir(&dir)?;
+    Ok(dir)
+}
+
+/// Scratch dir in the runtime dir, so files aren't shared with other users
+pub fn get_private_tmp_dir() -> Result<PathBuf, Box<dyn Error>> {
+    let dir = get_runtime_dir()?.join("tmp");
+    create_private_dir(&dir)?;
+    Ok(dir)
+}
+
+#[deprecated(note = "use `get_private_tmp_dir`, which doesn't share files with other users")]
 pub fn get_tmp_dir() -> PathBuf {
-    return PathBuf::from("/tmp");
+    get_private_tmp_dir().unwrap_or_else(|_| PathBuf::from("/tmp"))
+}
+
+/// Forms waiting to be shown or submitted. See [`forms`](super::forms)
+pub fn get_forms_dir() -> Result<PathBuf, Box<dyn Error>> {
+    let dir = get_runtime_dir()?.join("forms");
+    create_private_dir(&dir)?;
+    Ok(dir)
+}
+
+/// Creates the dir with 0700 permissions, so there's no moment where others can read it
+fn create_private_dir(dir: &Path) -> Result<(), Box<dyn Error>> {
+    if !dir.exists() {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "testing")]
+fn get_base_dir(name: &str, dir: Option<PathBuf>) -> Option<PathBuf> {
+    match FAKE_ROOT.with(|root| root.borrow().clone()) {
+        Some(root) => Some(root.join(name)),
+        None => dir,
+    }
+}
+
+#[cfg(not(feature = "testing"))]
+fn get_base_dir(_name: &str, dir: Option<PathBuf>) -> Option<PathBuf> {
+    dir
 }
 
 pub fn get_assets_dir() -> Result<PathBuf, Box<dyn Error>> {
@@ -62,6 +129,37 @@ pub fn get_extensions_dir() -> Result<PathBuf, Box<dyn Error>> {
     Ok(dir)
 }
 
+/// Where an extension can keep its own files
+pub fn get_extension_data_dir(extension_id: &str) -> Result<PathBuf, Box<dyn Error>> {
+    check_extension_id(extension_id)?;
+    let dir = get_local_dir()?.join("extension-data").join(extension_id);
+
+    if !dir.exists() {
+        fs::create_dir_all(&dir)?;
+    }
+
+    Ok(dir)
+}
+
+/// Ids end up in dir names, so they can't point outside the dir they're joined to
+pub fn check_extension_id(extension_id: &str) -> Result<(), Box<dyn Error>> {
+    if matches!(extension_id, "" | "." | "..") || extension_id.contains(['/', '\\', '\0']) {
+        return Err(format!("Invalid extension id {extension_id:?}").into());
+    }
+
+    Ok(())
+}
+
+pub fn get_cache_dir() -> Result<PathBuf, Box<dyn Error>> {
+    let dir = get_local_dir()?.join("cache");
+
+    if !dir.exists() {
+        fs::create_dir_all(&dir)?;
+    }
+
+    Ok(dir)
+}
+
 pub fn get_store_dir() -> Result<PathBuf, Box<dyn Error>> {
     let dir = get_local_dir()?.join("store");
 
@@ -80,6 +178,18 @@ pub fn get_extensions_path() -> Result<PathBuf, Box<dyn Error>> {
     Ok(get_local_dir()?.join("indexing").join("extensions.bin"))
 }
 
+pub fn get_result_cache_path() -> Result<PathBuf, Box<dyn Error>> {
+    Ok(get_cache_dir()?.join("results.bin"))
+}
+
+pub fn get_frecency_path() -> Result<PathBuf, Box<dyn Error>> {
+    Ok(get_local_dir()?.join("frecency.bin"))
+}
+
+pub fn get_jobs_status_path() -> Result<PathBuf, Box<dyn Error>> {
+    Ok(get_local_dir()?.join("jobs.bin"))
+}
+
 pub fn get_settings_path() -> Result<PathBuf, Box<dyn Error>> {
     Ok(get_config_dir()?.join("settings.bin"))
 }
@@ -92,6 +202,40 @@ pub fn get_themes_store_path() -> Result<PathBuf, Box<dyn Error>> {
     Ok(get_store_dir()?.join("themes.bin"))
 }
 
+pub fn get_control_socket_path() -> Result<PathBuf, Box<dyn Error>> {
+    Ok(get_runtime_dir()?.join("control.sock"))
+}
+
+/// File of the form with the given key. See [`forms`](super::forms)
+pub fn get_form_file_path(form_key: &str) -> Result<PathBuf, Box<dyn Error>> {
+    Ok(get_forms_dir()?.join(format!("{form_key}.form")))
+}
+
+/// The single form file from before forms had keys
+#[deprecated(
+    note = "use `forms::write_form` and `forms::read_form`, which give each form its own file"
+)]
+#[allow(deprecated)]
 pub fn get_form_path() -> PathBuf {
-    PathBuf::from("/tmp/tigris-form.bin")
+    get_tmp_dir().join("tigris-form.bin")
+}
+
+pub fn get_form_results_path(form_key: &str) -> Result<PathBuf, Box<dyn Error>> {
+    Ok(get_forms_dir()?.join(format!("{form_key}.results")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extension_ids_cant_leave_their_dir() {
+        for id in ["clipboard-history", "com.example.notes", "a..b", ".hidden"] {
+            assert!(check_extension_id(id).is_ok(), "{id}");
+        }
+
+        for id in ["", ".", "..", "../other", "a/b", "/abs", "a\\b", "a\0b"] {
+            assert!(check_extension_id(id).is_err(), "{id:?}");
+        }
+    }
 }
@@ -0,0 +1,366 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    io::{BufRead, ErrorKind, Read, Write},
+};
+
+use postcard::{from_bytes, to_allocvec};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{
+    api::{ExtensionRequest, ExtensionResponse},
+    search_results::SearchResult,
+};
+
+/// Version of the wire format spoken by this crate. Postcard is positional, so
+/// it has to be bumped whenever a type sent between host and extensions changes.
+/// Version 1 is the format from before versioning existed.
+pub const PROTOCOL_VERSION: u32 = 9;
+
+/// Set by the host to the protocol version it speaks
+pub const PROTOCOL_VERSION_ENV: &str = "TIGRIS_PROTOCOL_VERSION";
+
+/// Set by the host when it starts an extension as a long-lived daemon
+pub const DAEMON_ENV: &str = "TIGRIS_DAEMON";
+
+/// Set by the host for extensions that declare `streaming` in their manifest
+pub const STREAM_ENV: &str = "TIGRIS_STREAM";
+
+/// Largest request or frame read from the other side unless configured otherwise
+pub const MAX_FRAME_BYTES: usize = 8 * 1024 * 1024;
+
+/// Set to `json` to exchange JSON instead of postcard
+pub const ENCODING_ENV: &str = "TIGRIS_ENCODING";
+
+/// How requests and responses are written on stdin/stdout. JSON is slower but
+/// can be read and written by hand or from languages without a postcard library.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    #[default]
+    Postcard,
+    Json,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProtocolMismatch {
+    pub extension: u32,
+    pub host: u32,
+}
+
+/// Frames written by a streaming extension. A batch can be shown as soon as it
+/// arrives, `End` carries the final response and marks that nothing else is coming.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum StreamFrame {
+    Batch(Vec<SearchResult>),
+    End(ExtensionResponse),
+}
+
+/// Frames sent by the host to a daemon
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum DaemonMessage {
+    Request(Box<DaemonRequest>),
+    /// The host no longer needs the reply for this request id
+    Cancel(u64),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DaemonRequest {
+    pub id: u64,
+    pub request: ExtensionRequest,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DaemonResponse {
+    pub id: u64,
+    pub frame: StreamFrame,
+}
+
+// =================================================================
+// ==== Encoding
+// =================================================================
+
+impl Encoding {
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Box<dyn Error>> {
+        match self {
+            Encoding::Postcard => Ok(to_allocvec(value)?),
+            Encoding::Json => Ok(serde_json::to_vec(value)?),
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Box<dyn Error>> {
+        match self {
+            Encoding::Postcard => Ok(from_bytes(bytes)?),
+            Encoding::Json => Ok(serde_json::from_slice(bytes)?),
+        }
+    }
+
+    pub fn env_value(&self) -> &'static str {
+        match self {
+            Encoding::Postcard => "postcard",
+            Encoding::Json => "json",
+        }
+    }
+}
+
+/// Encoding chosen by the host through the environment, postcard if unset
+pub fn get_encoding() -> Encoding {
+    match std::env::var(ENCODING_ENV) {
+        Ok(value) if value.eq_ignore_ascii_case("json") => Encoding::Json,
+        _ => Encoding::Postcard,
+    }
+}
+
+// =================================================================
+// ==== Frames
+// =================================================================
+
+/// Writes a value as a frame. Postcard frames are a little-endian `u32` length
+/// followed by the bytes, JSON frames are one value per line.
+pub fn write_frame<T: Serialize>(
+    writer: &mut impl Write,
+    encoding: Encoding,
+    value: &T,
+) -> Result<(), Box<dyn Error>> {
+    writer.write_all(&encode_frame(encoding, value)?)?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Bytes written by [`write_frame`], for writers that aren't `Write`
+pub fn encode_frame<T: Serialize>(
+    encoding: Encoding,
+    value: &T,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let bytes = encoding.encode(value)?;
+
+    match encoding {
+        Encoding::Postcard => {
+            let length = u32::try_from(bytes.len()).map_err(|_| "Frame is too big")?;
+            Ok([length.to_le_bytes().as_slice(), &bytes].concat())
+        }
+        Encoding::Json => Ok([bytes.as_slice(), b"\n"].concat()),
+    }
+}
+
+/// Reads a frame written by [`write_frame`]. Returns `None` if the stream
+/// ended cleanly before a new frame and an error for frames over `max_bytes`.
+pub fn read_frame<T: DeserializeOwned>(
+    reader: &mut impl BufRead,
+    encoding: Encoding,
+    max_bytes: usize,
+) -> Result<Option<T>, Box<dyn Error>> {
+    if encoding == Encoding::Json {
+        return read_json_line(reader, max_bytes);
+    }
+
+    let mut length = [0u8; 4];
+    let mut read = 0;
+
+    while read < length.len() {
+        match reader.read(&mut length[read..]) {
+            Ok(0) if read == 0 => return Ok(None),
+            Ok(0) => return Err("Stream ended in the middle of a frame".into()),
+            Ok(n) => read += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let length = u32::from_le_bytes(length) as usize;
+
+    if length > max_bytes {
+        return Err(get_too_large_error(max_bytes));
+    }
+
+    let mut bytes = vec![0u8; length];
+    reader.read_exact(&mut bytes)?;
+
+    Ok(Some(from_bytes(&bytes)?))
+}
+
+fn read_json_line<T: DeserializeOwned>(
+    reader: &mut impl BufRead,
+    max_bytes: usize,
+) -> Result<Option<T>, Box<dyn Error>> {
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+
+        // One extra byte tells a line that is exactly the limit from a longer one
+        if reader.take(max_bytes as u64 + 1).read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        if line.len() > max_bytes {
+            return Err(get_too_large_error(max_bytes));
+        }
+
+        // Blank lines make hand written input easier to type
+        if !line.trim().is_empty() {
+            return Ok(Some(serde_json::from_str(&line)?));
+        }
+    }
+}
+
+/// Reads everything until the end of the stream, failing past `max_bytes`
+pub fn read_to_end_limited(reader: impl Read, max_bytes: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut bytes = vec![];
+    reader.take(max_bytes as u64 + 1).read_to_end(&mut bytes)?;
+
+    if bytes.len() > max_bytes {
+        return Err(get_too_large_error(max_bytes));
+    }
+
+    Ok(bytes)
+}
+
+fn get_too_large_error(max_bytes: usize) -> Box<dyn Error> {
+    format!("Frame is larger than the limit of {max_bytes} bytes").into()
+}
+
+pub fn is_daemon_mode() -> bool {
+    std::env::var(DAEMON_ENV).is_ok_and(|value| value == "1")
+}
+
+pub fn is_streaming_mode() -> bool {
+    std::env::var(STREAM_ENV).is_ok_and(|value| value == "1")
+}
+
+// =================================================================
+// ==== Versions
+// =================================================================
+
+/// Version announced by the host through the environment. Hosts from before
+/// versioning don't set it and speak version 1.
+pub fn get_host_protocol_version() -> u32 {
+    std::env::var(PROTOCOL_VERSION_ENV)
+        .ok()
+        .and_then(|version| version.parse().ok())
+        .unwrap_or(1)
+}
+
+/// Checks that an extension built for `extension_version` can talk to this crate
+pub fn check_protocol_version(extension_version: u32) -> Result<(), ProtocolMismatch> {
+    if extension_version == PROTOCOL_VERSION {
+        Ok(())
+    } else {
+        Err(ProtocolMismatch {
+            extension: extension_version,
+            host: PROTOCOL_VERSION,
+        })
+    }
+}
+
+/// Checks that the host that started this extension speaks the protocol it was built for
+pub fn check_host_protocol_version() -> Result<(), ProtocolMismatch> {
+    check_request_protocol_version(get_host_protocol_version())
+}
+
+/// Checks the version carried by a request received by this extension
+pub fn check_request_protocol_version(host_version: u32) -> Result<(), ProtocolMismatch> {
+    if host_version == PROTOCOL_VERSION {
+        Ok(())
+    } else {
+        Err(ProtocolMismatch {
+            extension: PROTOCOL_VERSION,
+            host: host_version,
+        })
+    }
+}
+
+impl Display for ProtocolMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Extension built for protocol {}, host speaks {}",
+            self.extension, self.host
+        )
+    }
+}
+
+impl Error for ProtocolMismatch {}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn read_all(bytes: &[u8], encoding: Encoding, max_bytes: usize) -> Vec<Option<String>> {
+        let mut reader = Cursor::new(bytes);
+
+        (0..3)
+            .map(|_| read_frame::<String>(&mut reader, encoding, max_bytes).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn frames_round_trip_in_both_encodings() {
+        for encoding in [Encoding::Postcard, Encoding::Json] {
+            let mut bytes = vec![];
+            write_frame(&mut bytes, encoding, &"first".to_string()).unwrap();
+            write_frame(&mut bytes, encoding, &"second line".to_string()).unwrap();
+
+            assert_eq!(
+                read_all(&bytes, encoding, MAX_FRAME_BYTES),
+                [Some("first".into()), Some("second line".into()), None],
+                "{encoding:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn frames_at_the_limit_are_read_and_over_it_rejected() {
+        for encoding in [Encoding::Postcard, Encoding::Json] {
+            let value = "abc".to_string();
+            let frame = encode_frame(encoding, &value).unwrap();
+
+            // Postcard limits the payload after the length, JSON the whole line
+            let size = match encoding {
+                Encoding::Postcard => frame.len() - 4,
+                Encoding::Json => frame.len(),
+            };
+
+            let mut reader = Cursor::new(&frame);
+            let read = read_frame::<String>(&mut reader, encoding, size).unwrap();
+            assert_eq!(read, Some(value), "{encoding:?}");
+
+            let mut reader = Cursor::new(&frame);
+            let error = read_frame::<String>(&mut reader, encoding, size - 1).unwrap_err();
+            assert!(
+                error.to_string().contains("larger than the limit"),
+                "{encoding:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_frames_are_errors() {
+        let mut reader = Cursor::new([3u8, 0]);
+        assert!(read_frame::<String>(&mut reader, Encoding::Postcard, MAX_FRAME_BYTES).is_err());
+
+        let mut reader = Cursor::new([10u8, 0, 0, 0, 1, 2]);
+        assert!(read_frame::<String>(&mut reader, Encoding::Postcard, MAX_FRAME_BYTES).is_err());
+    }
+
+    #[test]
+    fn empty_streams_end_cleanly() {
+        for encoding in [Encoding::Postcard, Encoding::Json] {
+            assert_eq!(read_all(&[], encoding, MAX_FRAME_BYTES), [None, None, None]);
+        }
+
+        assert_eq!(
+            read_all(b"\n\"a\"\n\n", Encoding::Json, MAX_FRAME_BYTES),
+            [Some("a".into()), None, None]
+        );
+    }
+
+    #[test]
+    fn reads_to_end_up_to_the_limit() {
+        assert_eq!(read_to_end_limited(&b"abcd"[..], 4).unwrap(), b"abcd");
+        assert!(read_to_end_limited(&b"abcde"[..], 4).is_err());
+    }
+}
@@ -0,0 +1,333 @@
+use std::fmt::Display;
+
+use super::{
+    extensions::Extension,
+    search::SearchQuery,
+    settings::{SearchEngine, Settings},
+};
+
+/// Where a query typed in the launcher goes
+#[derive(Debug, Clone, PartialEq)]
+pub enum Route {
+    /// The keyword belongs to a search engine, which gets the rest of the query
+    SearchEngine {
+        engine: SearchEngine,
+        search_text: String,
+    },
+    /// The keyword belongs to an extension, which gets the rest of the query
+    Extension {
+        extension_id: String,
+        keyword: String,
+        search_text: String,
+    },
+    /// No keyword matched, so the whole query goes to apps and to the
+    /// extensions without a keyword
+    Default { search_text: String },
+}
+
+/// Something that can own a keyword
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeywordOwner {
+    SearchEngine { id: usize, name: String },
+    Extension { id: String },
+}
+
+/// Keyword claimed by more than one owner. Owners are in order of precedence,
+/// the first one gets the queries.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeywordConflict {
+    pub keyword: String,
+    pub owners: Vec<KeywordOwner>,
+}
+
+/// Decides where queries go based on their keyword. Search engines take
+/// precedence over extensions, since their keywords are picked by the user,
+/// and extensions sharing a keyword are ordered by id.
+#[derive(Debug, Clone)]
+pub struct KeywordRouter {
+    /// In order of precedence
+    keywords: Vec<KeywordEntry>,
+    default_search_engine: Option<SearchEngine>,
+    default_extensions: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+struct KeywordEntry {
+    keyword: String,
+    owner: KeywordOwner,
+    target: KeywordTarget,
+}
+
+#[derive(Debug, Clone)]
+enum KeywordTarget {
+    SearchEngine(SearchEngine),
+    Extension(String),
+}
+
+// =================================================================
+// ==== Methods
+// =================================================================
+
+impl KeywordRouter {
+    /// Keywords are matched ignoring case. Empty ones and ones with whitespace
+    /// can never match, so they are skipped.
+    pub fn new(settings: &Settings, extensions: &[Extension]) -> Self {
+        let mut keywords = vec![];
+
+        for engine in &settings.search_engines {
+            if let Some(keyword) = normalize_keyword(&engine.keyword) {
+                keywords.push(KeywordEntry {
+                    keyword,
+                    owner: KeywordOwner::SearchEngine {
+                        id: engine.id,
+                        name: engine.name.to_owned(),
+                    },
+                    target: KeywordTarget::SearchEngine(engine.to_owned()),
+                });
+            }
+        }
+
+        let mut extensions: Vec<&Extension> = extensions.iter().collect();
+        extensions.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let mut default_extensions = vec![];
+
+        for extension in extensions {
+            match extension.keyword.as_deref().and_then(normalize_keyword) {
+                Some(keyword) => keywords.push(KeywordEntry {
+                    keyword,
+                    owner: KeywordOwner::Extension {
+                        id: extension.id.to_owned(),
+                    },
+                    target: KeywordTarget::Extension(extension.id.to_owned()),
+                }),
+                None => default_extensions.push(extension.id.to_owned()),
+            }
+        }
+
+        let default_search_engine = settings
+            .search_engines
+            .iter()
+            .find(|e| e.id == settings.default_search_engine)
+            .cloned();
+
+        Self {
+            keywords,
+            default_search_engine,
+            default_extensions,
+        }
+    }
+
+    pub fn route(&self, query: &str) -> Route {
+        let search_query = SearchQuery::from(query);
+
+        let entry = search_query.keyword.as_deref().and_then(|keyword| {
+            let keyword = normalize_keyword(keyword)?;
+            self.keywords.iter().find(|e| e.keyword == keyword)
+        });
+
+        match (entry, search_query.keyword) {
+            (Some(entry), Some(keyword)) => match &entry.target {
+                KeywordTarget::SearchEngine(engine) => Route::SearchEngine {
+                    engine: engine.to_owned(),
+                    search_text: search_query.search_text,
+                },
+                KeywordTarget::Extension(extension_id) => Route::Extension {
+                    extension_id: extension_id.to_owned(),
+                    keyword,
+                    search_text: search_query.search_text,
+                },
+            },
+            _ => Route::Default {
+                search_text: query.trim().to_owned(),
+            },
+        }
+    }
+
+    /// Keywords claimed by more than one search engine or extension
+    pub fn get_conflicts(&self) -> Vec<KeywordConflict> {
+        let mut conflicts: Vec<KeywordConflict> = vec![];
+
+        for entry in &self.keywords {
+            match conflicts.iter_mut().find(|c| c.keyword == entry.keyword) {
+                Some(conflict) => conflict.owners.push(entry.owner.to_owned()),
+                None => conflicts.push(KeywordConflict {
+                    keyword: entry.keyword.to_owned(),
+                    owners: vec![entry.owner.to_owned()],
+                }),
+            }
+        }
+
+        conflicts.retain(|conflict| conflict.owners.len() > 1);
+        conflicts
+    }
+
+    /// Ids of the extensions that get queries without a keyword
+    pub fn get_default_extensions(&self) -> &[String] {
+        &self.default_extensions
+    }
+
+    /// Engine offered when nothing else matches, as picked in the settings
+    pub fn get_default_search_engine(&self) -> Option<&SearchEngine> {
+        self.default_search_engine.as_ref()
+    }
+}
+
+fn normalize_keyword(keyword: &str) -> Option<String> {
+    if keyword.is_empty() || keyword.contains(char::is_whitespace) {
+        return None;
+    }
+
+    Some(keyword.to_lowercase())
+}
+
+impl Display for KeywordOwner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeywordOwner::SearchEngine { name, .. } => write!(f, "search engine {name}"),
+            KeywordOwner::Extension { id } => write!(f, "extension {id}"),
+        }
+    }
+}
+
+impl Display for KeywordConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let owners: Vec<String> = self.owners.iter().map(|o| o.to_string()).collect();
+
+        write!(
+            f,
+            "Keyword {} is used by {}, queries go to the first one",
+            self.keyword,
+            owners.join(", ")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Default settings, whose search engines include Google as `gs`
+    fn get_settings() -> Settings {
+        serde_json::from_str("{}").unwrap()
+    }
+
+    fn get_extension(id: &str, keyword: Option<&str>) -> Extension {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "name": id,
+            "description": "",
+            "settings": [],
+            "keyword": keyword,
+        }))
+        .unwrap()
+    }
+
+    fn get_engine(settings: &Settings, keyword: &str) -> SearchEngine {
+        settings
+            .search_engines
+            .iter()
+            .find(|engine| engine.keyword == keyword)
+            .cloned()
+            .unwrap()
+    }
+
+    fn extension_route(extension_id: &str, keyword: &str, search_text: &str) -> Route {
+        Route::Extension {
+            extension_id: extension_id.to_owned(),
+            keyword: keyword.to_owned(),
+            search_text: search_text.to_owned(),
+        }
+    }
+
+    fn default_route(search_text: &str) -> Route {
+        Route::Default {
+            search_text: search_text.to_owned(),
+        }
+    }
+
+    #[test]
+    fn search_engines_win_over_extensions_with_the_same_keyword() {
+        let settings = get_settings();
+        let router = KeywordRouter::new(&settings, &[get_extension("google", Some("GS"))]);
+
+        assert_eq!(
+            router.route("gs rust"),
+            Route::SearchEngine {
+                engine: get_engine(&settings, "gs"),
+                search_text: "rust".to_owned(),
+            }
+        );
+
+        let conflicts = router.get_conflicts();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].keyword, "gs");
+        assert_eq!(
+            conflicts[0].owners,
+            [
+                KeywordOwner::SearchEngine {
+                    id: 1,
+                    name: "Google".to_owned()
+                },
+                KeywordOwner::Extension {
+                    id: "google".to_owned()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn extensions_sharing_a_keyword_are_ordered_by_id() {
+        let extensions = [
+            get_extension("zeta", Some("gh")),
+            get_extension("alpha", Some("gh")),
+        ];
+        let router = KeywordRouter::new(&get_settings(), &extensions);
+
+        assert_eq!(
+            router.route("GH tigris core"),
+            extension_route("alpha", "GH", "tigris core")
+        );
+
+        let owners: Vec<String> = router.get_conflicts()[0]
+            .owners
+            .iter()
+            .map(|owner| owner.to_string())
+            .collect();
+
+        assert_eq!(owners, ["extension alpha", "extension zeta"]);
+    }
+
+    #[test]
+    fn keywords_need_whitespace_after_them() {
+        let settings = get_settings();
+        let router = KeywordRouter::new(&settings, &[get_extension("github", Some("gh"))]);
+
+        assert_eq!(router.route("gs"), default_route("gs"));
+        assert_eq!(
+            router.route("gs "),
+            Route::SearchEngine {
+                engine: get_engine(&settings, "gs"),
+                search_text: String::new(),
+            }
+        );
+
+        assert_eq!(router.route("gh"), default_route("gh"));
+        assert_eq!(router.route("gh "), extension_route("github", "gh", ""));
+    }
+
+    #[test]
+    fn unknown_keywords_fall_back_to_the_default_route() {
+        let extensions = [
+            get_extension("github", Some("gh")),
+            get_extension("calculator", None),
+            get_extension("spaced", Some("a b")),
+        ];
+        let router = KeywordRouter::new(&get_settings(), &extensions);
+
+        assert_eq!(router.route(" xy rust "), default_route("xy rust"));
+        assert_eq!(router.route("a b c"), default_route("a b c"));
+        assert_eq!(router.get_default_extensions(), ["calculator", "spaced"]);
+        assert!(router.get_conflicts().is_empty());
+    }
+}
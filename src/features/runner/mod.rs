@@ -0,0 +1,694 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    io::{BufReader, Read, Write},
+    path::PathBuf,
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::{
+    actions::RunExtensionAction,
+    api::{ActionResponse, ExtensionRequest, ExtensionResponse, FormResult, RequestType},
+    cache::SharedCache,
+    cancellation::CancelToken,
+    extensions::{
+        check_action_caller, get_extension_dir, get_extension_manifest, CachePolicy, PublicAction,
+    },
+    protocol::{
+        read_frame, Encoding, ProtocolMismatch, StreamFrame, ENCODING_ENV, PROTOCOL_VERSION,
+        PROTOCOL_VERSION_ENV, STREAM_ENV,
+    },
+    sandbox::{apply_sandbox, SandboxPolicy},
+    search_results::SearchResult,
+    validation::{OutputLimits, ResultValidator, ValidationReport},
+};
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// How long the pipes are still read after the extension exits. A process it
+/// started may have inherited them and keep them open.
+const PIPE_GRACE: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+pub enum RunnerError {
+    /// The extension or its binary could not be found
+    NotFound(String),
+    Spawn(std::io::Error),
+    Io(std::io::Error),
+    /// The extension exited with a non-zero status
+    Crashed {
+        status: ExitStatus,
+        stderr: String,
+    },
+    /// The extension didn't finish before the deadline and was killed
+    TimedOut {
+        timeout: Duration,
+        stderr: String,
+    },
+    /// The extension replied with something that isn't a valid response
+    InvalidOutput {
+        error: String,
+        stderr: String,
+    },
+    /// The extension handled the request but returned an error
+    Failed(String),
+    /// The request was cancelled before the extension replied
+    Cancelled,
+    /// The extension was built for a different protocol version than the host
+    IncompatibleProtocol(ProtocolMismatch),
+    /// Another extension tried to run an action it isn't allowed to
+    PermissionDenied(String),
+}
+
+/// Output of a pipe, read on its own thread
+struct PipeReader {
+    buffer: Arc<Mutex<Vec<u8>>>,
+    /// Disconnects when the pipe is closed
+    closed: Receiver<()>,
+}
+
+/// Spawns an extension binary for each request and collects its reply.
+#[derive(Debug, Clone)]
+pub struct ExtensionRunner {
+    pub extension_id: String,
+    pub extension_dir: PathBuf,
+    pub binary_path: PathBuf,
+    pub timeout: Duration,
+    pub daemon: bool,
+    pub streaming: bool,
+    pub encoding: Encoding,
+    pub limits: OutputLimits,
+    /// Restrictions applied to the process, `None` runs it unrestricted
+    pub sandbox: Option<SandboxPolicy>,
+    /// Actions other extensions can run
+    pub public_actions: Vec<PublicAction>,
+    pub cache_policy: Option<CachePolicy>,
+    /// Where results are cached, `None` runs the extension for every query
+    pub cache: Option<SharedCache>,
+    /// Called with what was dropped from a response, `None` drops the reports
+    pub on_report: Option<ReportHandler>,
+}
+
+/// Receives the id of an extension and what was dropped from its response, see
+/// [`ExtensionRunner::set_report_handler`]
+#[derive(Clone)]
+pub struct ReportHandler(Arc<ReportFn>);
+
+type ReportFn = dyn Fn(&str, &ValidationReport) + Send + Sync;
+
+// =================================================================
+// ==== Methods
+// =================================================================
+
+impl ExtensionRunner {
+    pub fn new(extension_id: &str) -> Result<Self, RunnerError> {
+        let extension_dir = get_extension_dir(extension_id)
+            .map_err(|e| RunnerError::NotFound(format!("{extension_id}: {e}")))?;
+
+        let manifest = get_extension_manifest(&extension_dir)
+            .map_err(|e| RunnerError::NotFound(format!("{extension_id}: {e}")))?;
+
+        manifest
+            .check_protocol_version()
+            .map_err(RunnerError::IncompatibleProtocol)?;
+
+        let binary_path = extension_dir.join(&manifest.binary);
+
+        if !binary_path.is_file() {
+            return Err(RunnerError::NotFound(format!(
+                "{extension_id}: missing binary {}",
+                binary_path.display()
+            )));
+        }
+
+        Ok(Self {
+            extension_id: extension_id.to_owned(),
+            extension_dir,
+            binary_path,
+            timeout: DEFAULT_TIMEOUT,
+            daemon: manifest.daemon,
+            streaming: manifest.streaming,
+            encoding: manifest.encoding,
+            limits: OutputLimits::default(),
+            sandbox: manifest.sandbox,
+            public_actions: manifest.public_actions,
+            cache_policy: manifest.cache,
+            cache: None,
+            on_report: None,
+        })
+    }
+
+    pub fn set_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Overrides the encoding from the manifest, e.g. to debug an extension with JSON
+    pub fn set_encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Overrides the policy from the manifest, e.g. to sandbox every store extension
+    pub fn set_sandbox(mut self, sandbox: Option<SandboxPolicy>) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    pub fn set_limits(mut self, limits: OutputLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Reuses results according to the manifest's cache policy and the TTLs of
+    /// responses. Without a cache they are never reused.
+    pub fn set_cache(mut self, cache: &SharedCache) -> Self {
+        self.cache = Some(Arc::clone(cache));
+        self
+    }
+
+    /// Sets the handler called when results are dropped, e.g. to log them or
+    /// show them to the extension's developer. Without one nothing is reported.
+    pub fn set_report_handler(
+        mut self,
+        on_report: impl Fn(&str, &ValidationReport) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_report = Some(ReportHandler(Arc::new(on_report)));
+        self
+    }
+
+    pub fn get_results(&self, search_text: &str) -> Result<ExtensionResponse, RunnerError> {
+        self.get_results_with_cancel(search_text, &CancelToken::new())
+    }
+
+    /// Same as [`ExtensionRunner::get_results`] but kills the extension as soon
+    /// as the token is cancelled, for searches superseded by newer input.
+    pub fn get_results_with_cancel(
+        &self,
+        search_text: &str,
+        cancel: &CancelToken,
+    ) -> Result<ExtensionResponse, RunnerError> {
+        let request = ExtensionRequest::new_get_results_request(search_text);
+        self.send_with_cancel(&request, cancel)
+    }
+
+    /// Calls `on_batch` with each batch of results as soon as the extension sends
+    /// it and returns the final response with all of them merged. Extensions that
+    /// don't stream produce a single batch.
+    pub fn stream_results(
+        &self,
+        search_text: &str,
+        cancel: &CancelToken,
+        on_batch: impl FnMut(&[SearchResult]),
+    ) -> Result<ExtensionResponse, RunnerError> {
+        let request = ExtensionRequest::new_get_results_request(search_text);
+        self.stream_request(&request, cancel, on_batch)
+    }
+
+    /// Same as [`ExtensionRunner::stream_results`] for a request built by the
+    /// launcher, e.g. one with a [`QueryContext`](super::api::QueryContext).
+    pub fn stream_request(
+        &self,
+        request: &ExtensionRequest,
+        cancel: &CancelToken,
+        mut on_batch: impl FnMut(&[SearchResult]),
+    ) -> Result<ExtensionResponse, RunnerError> {
+        if !self.streaming {
+            let response = self.send_with_cancel(request, cancel)?;
+            on_batch(response.get_results());
+            return Ok(response);
+        }
+
+        if let Some(response) = self.get_cached(request) {
+            on_batch(response.get_results());
+            return Ok(response);
+        }
+
+        let bytes = self.encode_request(request)?;
+
+        let mut child = self
+            .command()?
+            .env(STREAM_ENV, "1")
+            .spawn()
+            .map_err(RunnerError::Spawn)?;
+
+        write_stdin(&mut child, bytes);
+        let stderr_reader = self.read_pipe(child.stderr.take().expect("stderr is piped"));
+        let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        let encoding = self.encoding;
+        let max_frame_bytes = self.limits.max_frame_bytes;
+
+        let (sender, frames) = mpsc::channel();
+
+        thread::spawn(move || loop {
+            let frame = read_frame::<StreamFrame>(&mut stdout, encoding, max_frame_bytes)
+                .map_err(|e| e.to_string());
+            let last = !matches!(frame, Ok(Some(StreamFrame::Batch(_))));
+
+            if sender.send(frame).is_err() || last {
+                break;
+            }
+        });
+
+        let deadline = Instant::now() + self.timeout;
+        let mut validator = ResultValidator::new(&self.limits);
+        let mut partial_results = vec![];
+        let mut response = None;
+        let mut invalid_output = None;
+        let mut exited_at = None;
+
+        loop {
+            if cancel.is_cancelled() || Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                let stderr = stderr_reader.finish_lossy(Instant::now() + PIPE_GRACE);
+
+                return Err(if cancel.is_cancelled() {
+                    RunnerError::Cancelled
+                } else {
+                    RunnerError::TimedOut {
+                        timeout: self.timeout,
+                        stderr,
+                    }
+                });
+            }
+
+            match frames.recv_timeout(POLL_INTERVAL) {
+                Ok(Ok(Some(StreamFrame::Batch(batch)))) => {
+                    let batch = validator.filter(batch);
+
+                    if !batch.is_empty() {
+                        on_batch(&batch);
+                    }
+
+                    partial_results.extend(batch);
+                }
+                Ok(Ok(Some(StreamFrame::End(end)))) => {
+                    let end = validator.filter_response(end);
+
+                    if !end.get_results().is_empty() {
+                        on_batch(end.get_results());
+                    }
+
+                    response = Some(end.merge_partial_results(partial_results));
+                    break;
+                }
+                Ok(Ok(None)) => break,
+                Ok(Err(error)) => {
+                    invalid_output = Some(error);
+                    break;
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    // Stop waiting for the end of a stream held open by another process
+                    if let Ok(Some(_)) = child.try_wait() {
+                        let exited_at = *exited_at.get_or_insert_with(Instant::now);
+
+                        if exited_at.elapsed() >= PIPE_GRACE {
+                            break;
+                        }
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let status = wait_with_deadline(&mut child, remaining, cancel);
+        let stderr = stderr_reader.finish_lossy(Instant::now() + PIPE_GRACE);
+        self.report(validator.into_report());
+
+        match (status.map_err(RunnerError::Io)?, response) {
+            (None, _) if cancel.is_cancelled() => Err(RunnerError::Cancelled),
+            (None, _) => Err(RunnerError::TimedOut {
+                timeout: self.timeout,
+                stderr,
+            }),
+            (Some(status), _) if !status.success() => Err(RunnerError::Crashed { status, stderr }),
+            (Some(_), None) => Err(RunnerError::InvalidOutput {
+                error: invalid_output
+                    .unwrap_or_else(|| "Stream ended without an end marker".to_string()),
+                stderr,
+            }),
+            (Some(_), Some(response)) => Ok(self.update_cache(request, response)),
+        }
+    }
+
+    pub fn run_action(
+        &self,
+        action: &str,
+        args: &Vec<String>,
+    ) -> Result<ActionResponse, RunnerError> {
+        let request = ExtensionRequest::new_run_extension_action_request(action, args);
+        get_action_response(self.send(&request)?)
+    }
+
+    /// Runs an action on behalf of another extension if the manifest allows it
+    pub fn run_action_from(
+        &self,
+        caller_id: &str,
+        action: &str,
+        args: &Vec<String>,
+    ) -> Result<ActionResponse, RunnerError> {
+        self.check_action_caller(action, caller_id)?;
+
+        let request = ExtensionRequest::new_cross_extension_action_request(caller_id, action, args);
+        get_action_response(self.send(&request)?)
+    }
+
+    pub fn check_action_caller(&self, action: &str, caller_id: &str) -> Result<(), RunnerError> {
+        check_action_caller(&self.extension_id, &self.public_actions, action, caller_id)
+            .map_err(RunnerError::PermissionDenied)
+    }
+
+    pub fn send_form_results(
+        &self,
+        form_id: &str,
+        results: &Vec<FormResult>,
+        args: &Vec<String>,
+    ) -> Result<ActionResponse, RunnerError> {
+        let request = ExtensionRequest::new_form_results_request(form_id, results, args);
+        get_action_response(self.send(&request)?)
+    }
+
+    /// Runs the extension with the given request and waits for its response,
+    /// killing it if it goes past the timeout.
+    pub fn send(&self, request: &ExtensionRequest) -> Result<ExtensionResponse, RunnerError> {
+        self.send_with_cancel(request, &CancelToken::new())
+    }
+
+    pub fn send_with_cancel(
+        &self,
+        request: &ExtensionRequest,
+        cancel: &CancelToken,
+    ) -> Result<ExtensionResponse, RunnerError> {
+        if let Some(response) = self.get_cached(request) {
+            return Ok(response);
+        }
+
+        let bytes = self.encode_request(request)?;
+
+        let mut child = self.command()?.spawn().map_err(RunnerError::Spawn)?;
+
+        write_stdin(&mut child, bytes);
+        let stdout_reader = self.read_pipe(child.stdout.take().expect("stdout is piped"));
+        let stderr_reader = self.read_pipe(child.stderr.take().expect("stderr is piped"));
+
+        let status = wait_with_deadline(&mut child, self.timeout, cancel);
+
+        let pipes_deadline = Instant::now() + PIPE_GRACE;
+        let stdout = stdout_reader.finish(pipes_deadline);
+        let stderr = stderr_reader.finish_lossy(pipes_deadline);
+
+        match status.map_err(RunnerError::Io)? {
+            None if cancel.is_cancelled() => Err(RunnerError::Cancelled),
+            None => Err(RunnerError::TimedOut {
+                timeout: self.timeout,
+                stderr,
+            }),
+            Some(status) if !status.success() => Err(RunnerError::Crashed { status, stderr }),
+            Some(_) if stdout.len() > self.limits.max_frame_bytes => {
+                Err(RunnerError::InvalidOutput {
+                    error: format!(
+                        "Output is larger than the limit of {} bytes",
+                        self.limits.max_frame_bytes
+                    ),
+                    stderr,
+                })
+            }
+            Some(_) => {
+                let response =
+                    self.encoding
+                        .decode(&stdout)
+                        .map_err(|e| RunnerError::InvalidOutput {
+                            error: e.to_string(),
+                            stderr,
+                        })?;
+
+                Ok(self.update_cache(request, self.validate(response)))
+            }
+        }
+    }
+
+    /// Drops invalid results and the ones over the limit, reporting them
+    pub(crate) fn validate(&self, response: ExtensionResponse) -> ExtensionResponse {
+        let mut validator = ResultValidator::new(&self.limits);
+        let response = validator.filter_response(response);
+        self.report(validator.into_report());
+
+        response
+    }
+
+    /// Cached reply to a `GetResults` request, if there's one that hasn't expired
+    pub(crate) fn get_cached(&self, request: &ExtensionRequest) -> Option<ExtensionResponse> {
+        let get_results_request = request.get_results_request.as_ref()?;
+
+        if request.request_type != RequestType::GetResults {
+            return None;
+        }
+
+        let cache = self.cache.as_ref()?.lock().ok()?;
+        cache.get(&self.extension_id, get_results_request)
+    }
+
+    /// Caches the results of a query or drops the extension's results after an
+    /// action, depending on the policy. Returns the response the launcher gets,
+    /// with `CachedResults` turned into `Results`.
+    pub(crate) fn update_cache(
+        &self,
+        request: &ExtensionRequest,
+        response: ExtensionResponse,
+    ) -> ExtensionResponse {
+        let (response, response_ttl) = response.split_cache_ttl();
+
+        let Some(mut cache) = self.cache.as_ref().and_then(|c| c.lock().ok()) else {
+            return response;
+        };
+
+        match (&request.request_type, &request.get_results_request) {
+            (RequestType::GetResults, Some(get_results_request)) if !response.is_error() => {
+                let ttl = response_ttl.or(self.cache_policy.map(|p| p.ttl_secs));
+
+                if let Some(ttl) = ttl.filter(|ttl| *ttl > 0) {
+                    cache.insert(
+                        &self.extension_id,
+                        get_results_request,
+                        &response,
+                        Duration::from_secs(ttl),
+                        self.cache_policy.is_some_and(|p| p.persist),
+                    );
+                }
+            }
+            (RequestType::RunAction | RequestType::FormResults, _)
+                if self.cache_policy.is_some_and(|p| p.invalidate_on_action) =>
+            {
+                cache.invalidate(&self.extension_id);
+            }
+            _ => {}
+        }
+
+        response
+    }
+
+    pub(crate) fn report(&self, report: ValidationReport) {
+        if let Some(ReportHandler(on_report)) = &self.on_report {
+            if !report.is_empty() {
+                on_report(&self.extension_id, &report);
+            }
+        }
+    }
+
+    /// Reads a pipe up to one byte over the frame limit so oversized output can be
+    /// told apart. The rest is thrown away so the extension doesn't block on writes.
+    fn read_pipe(&self, mut pipe: impl Read + Send + 'static) -> PipeReader {
+        let max_bytes = self.limits.max_frame_bytes.saturating_add(1);
+        let buffer = Arc::new(Mutex::new(vec![]));
+        let writer = Arc::clone(&buffer);
+        let (closed_sender, closed) = mpsc::channel::<()>();
+
+        thread::spawn(move || {
+            let _closed_sender = closed_sender;
+            let mut chunk = [0u8; 8192];
+
+            loop {
+                match pipe.read(&mut chunk) {
+                    Ok(0) => break,
+                    Ok(read) => {
+                        let mut buffer = writer.lock().unwrap();
+                        let kept = read.min(max_bytes.saturating_sub(buffer.len()));
+                        buffer.extend_from_slice(&chunk[..kept]);
+                    }
+                    Err(error) if error.kind() == std::io::ErrorKind::Interrupted => {}
+                    Err(_) => break,
+                }
+            }
+        });
+
+        PipeReader { buffer, closed }
+    }
+
+    pub(crate) fn command(&self) -> Result<Command, RunnerError> {
+        let mut command = Command::new(&self.binary_path);
+        command.current_dir(&self.extension_dir);
+
+        if let Some(sandbox) = &self.sandbox {
+            apply_sandbox(
+                &mut command,
+                sandbox,
+                &self.extension_id,
+                &self.extension_dir,
+            )
+            .map_err(RunnerError::Spawn)?;
+        }
+
+        command
+            .env(PROTOCOL_VERSION_ENV, PROTOCOL_VERSION.to_string())
+            .env(ENCODING_ENV, self.encoding.env_value())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        Ok(command)
+    }
+
+    fn encode_request(&self, request: &ExtensionRequest) -> Result<Vec<u8>, RunnerError> {
+        self.encoding.encode(request).map_err(|e| {
+            RunnerError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                e.to_string(),
+            ))
+        })
+    }
+}
+
+/// Waits for the child to exit. Returns `None` if it had to be killed because
+/// of the deadline or the token.
+pub(crate) fn wait_with_deadline(
+    child: &mut Child,
+    timeout: Duration,
+    cancel: &CancelToken,
+) -> Result<Option<ExitStatus>, std::io::Error> {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+
+        if Instant::now() >= deadline || cancel.is_cancelled() {
+            let _ = child.kill();
+            child.wait()?;
+            return Ok(None);
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Runs a `RunExtension` action from a result of `caller_id`. Actions targeting
+/// the caller itself run as usual, others have to be public in the target's manifest.
+pub fn run_extension_action(
+    caller_id: &str,
+    action: &RunExtensionAction,
+) -> Result<ActionResponse, RunnerError> {
+    let runner = ExtensionRunner::new(&action.extension_id)?;
+
+    if action.extension_id == caller_id {
+        runner.run_action(&action.extension_action, &action.args)
+    } else {
+        runner.run_action_from(caller_id, &action.extension_action, &action.args)
+    }
+}
+
+/// Reads what the launcher should do from the reply to an action or form, see
+/// [`ExtensionResponse::into_action_response`]
+pub(crate) fn get_action_response(
+    response: ExtensionResponse,
+) -> Result<ActionResponse, RunnerError> {
+    response.into_action_response().map_err(RunnerError::Failed)
+}
+
+/// Writes the request on its own thread, which isn't joined: a process started
+/// by the extension could hold stdin open without reading it. A failed write
+/// only means the extension exited without reading.
+fn write_stdin(child: &mut Child, bytes: Vec<u8>) {
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    thread::spawn(move || stdin.write_all(&bytes));
+}
+
+impl PipeReader {
+    /// Waits until the pipe is closed or the deadline passes and returns what
+    /// was read so far
+    fn finish(self, deadline: Instant) -> Vec<u8> {
+        let _ = self
+            .closed
+            .recv_timeout(deadline.saturating_duration_since(Instant::now()));
+
+        std::mem::take(&mut *self.buffer.lock().unwrap())
+    }
+
+    fn finish_lossy(self, deadline: Instant) -> String {
+        String::from_utf8_lossy(&self.finish(deadline))
+            .trim()
+            .to_owned()
+    }
+}
+
+impl RunnerError {
+    /// Error response the launcher can show in place of the extension's results
+    pub fn to_response(&self) -> ExtensionResponse {
+        let details = match self {
+            RunnerError::Crashed { stderr, .. }
+            | RunnerError::TimedOut { stderr, .. }
+            | RunnerError::InvalidOutput { stderr, .. }
+                if !stderr.is_empty() =>
+            {
+                Some(stderr.to_owned())
+            }
+            _ => None,
+        };
+
+        ExtensionResponse::Error {
+            message: self.to_string(),
+            details,
+        }
+    }
+}
+
+impl std::fmt::Debug for ReportHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ReportHandler")
+    }
+}
+
+impl Display for RunnerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RunnerError::NotFound(message) => write!(f, "Extension not found: {message}"),
+            RunnerError::Spawn(error) => write!(f, "Error starting extension: {error}"),
+            RunnerError::Io(error) => write!(f, "Error talking to extension: {error}"),
+            RunnerError::Crashed { status, stderr } => {
+                write!(f, "Extension crashed ({status}): {stderr}")
+            }
+            RunnerError::TimedOut { timeout, .. } => {
+                write!(f, "Extension timed out after {}ms", timeout.as_millis())
+            }
+            RunnerError::InvalidOutput { error, .. } => {
+                write!(f, "Extension returned an invalid response: {error}")
+            }
+            RunnerError::Failed(message) => write!(f, "Extension failed: {message}"),
+            RunnerError::Cancelled => write!(f, "Extension request was cancelled"),
+            RunnerError::IncompatibleProtocol(mismatch) => write!(f, "{mismatch}"),
+            RunnerError::PermissionDenied(message) => write!(f, "Permission denied: {message}"),
+        }
+    }
+}
+
+impl Error for RunnerError {}
@@ -0,0 +1,174 @@
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "host")]
+use {
+    super::paths::get_extension_data_dir, std::fs, std::io::Error,
+    std::os::unix::fs::PermissionsExt, std::os::unix::process::CommandExt, std::path::PathBuf,
+    std::process::Command,
+};
+
+/// Variables kept in a scrubbed environment on top of the ones the host sets,
+/// so extensions can still find the launcher settings and the runtime dir
+#[cfg(feature = "host")]
+const BASE_ENV: [&str; 9] = [
+    "PATH",
+    "HOME",
+    "LANG",
+    "LC_ALL",
+    "LC_MESSAGES",
+    "XDG_CONFIG_HOME",
+    "XDG_DATA_HOME",
+    "XDG_RUNTIME_DIR",
+    "XDG_SESSION_TYPE",
+];
+
+/// Type of the resource argument of `setrlimit`, which differs between libcs
+#[cfg(all(feature = "host", target_env = "gnu"))]
+type Resource = libc::__rlimit_resource_t;
+
+#[cfg(all(feature = "host", not(target_env = "gnu")))]
+type Resource = libc::c_int;
+
+/// Set in sandboxed extensions since they don't start in their own dir
+pub const EXTENSION_DIR_ENV: &str = "TIGRIS_EXTENSION_DIR";
+
+/// Restrictions for running an extension, declared in its manifest. A sandboxed
+/// extension runs with a scrubbed environment, in a private working dir under its
+/// data dir and without being able to gain privileges. Limits only apply if set.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct SandboxPolicy {
+    /// CPU time in seconds. For daemons this counts over the whole process life
+    #[serde(default)]
+    pub max_cpu_seconds: Option<u64>,
+
+    /// Address space in megabytes
+    #[serde(default)]
+    pub max_memory_mb: Option<u64>,
+
+    #[serde(default)]
+    pub max_open_files: Option<u64>,
+
+    /// Extra environment variables passed through, e.g. `DISPLAY`
+    #[serde(default)]
+    pub allowed_env: Vec<String>,
+}
+
+// =================================================================
+// ==== Methods
+// =================================================================
+
+impl SandboxPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_max_cpu_seconds(mut self, seconds: u64) -> Self {
+        self.max_cpu_seconds = Some(seconds);
+        self
+    }
+
+    pub fn set_max_memory_mb(mut self, megabytes: u64) -> Self {
+        self.max_memory_mb = Some(megabytes);
+        self
+    }
+
+    pub fn set_max_open_files(mut self, files: u64) -> Self {
+        self.max_open_files = Some(files);
+        self
+    }
+
+    pub fn allow_env(mut self, name: &str) -> Self {
+        self.allowed_env.push(name.to_owned());
+        self
+    }
+}
+
+// =================================================================
+// ==== Host
+// =================================================================
+
+/// Private working dir of a sandboxed extension, only readable by the user
+#[cfg(feature = "host")]
+pub fn get_sandbox_dir(extension_id: &str) -> Result<PathBuf, Error> {
+    let dir = get_extension_data_dir(extension_id)
+        .map_err(|e| Error::other(e.to_string()))?
+        .join("sandbox");
+
+    fs::create_dir_all(&dir)?;
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))?;
+
+    Ok(dir)
+}
+
+/// Applies the policy to a command before it's spawned. Has to be called before
+/// the host sets its own variables since the environment is cleared.
+#[cfg(feature = "host")]
+pub(crate) fn apply_sandbox(
+    command: &mut Command,
+    policy: &SandboxPolicy,
+    extension_id: &str,
+    extension_dir: &std::path::Path,
+) -> Result<(), Error> {
+    let sandbox_dir = get_sandbox_dir(extension_id)?;
+
+    command.env_clear();
+
+    for name in BASE_ENV
+        .iter()
+        .copied()
+        .chain(policy.allowed_env.iter().map(|n| n.as_str()))
+    {
+        if let Some(value) = std::env::var_os(name) {
+            command.env(name, value);
+        }
+    }
+
+    command
+        .current_dir(&sandbox_dir)
+        .env("TMPDIR", &sandbox_dir)
+        .env(EXTENSION_DIR_ENV, extension_dir);
+
+    let policy = policy.to_owned();
+
+    // Runs in the child between fork and exec, so it only makes plain syscalls
+    unsafe {
+        command.pre_exec(move || apply_limits(&policy));
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "host")]
+fn apply_limits(policy: &SandboxPolicy) -> Result<(), Error> {
+    if let Some(seconds) = policy.max_cpu_seconds {
+        set_limit(libc::RLIMIT_CPU, seconds)?;
+    }
+
+    if let Some(megabytes) = policy.max_memory_mb {
+        set_limit(libc::RLIMIT_AS, megabytes.saturating_mul(1024 * 1024))?;
+    }
+
+    if let Some(files) = policy.max_open_files {
+        set_limit(libc::RLIMIT_NOFILE, files)?;
+    }
+
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "host")]
+fn set_limit(resource: Resource, value: u64) -> Result<(), Error> {
+    let limit = libc::rlimit {
+        rlim_cur: value as libc::rlim_t,
+        rlim_max: value as libc::rlim_t,
+    };
+
+    if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(())
+}
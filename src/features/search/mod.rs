@@ -4,34 +4,216 @@ pub struct SearchQuery {
     pub search_text: String,
 }
 
+/// Part of the search text, see [`tokenize`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryToken {
+    Word(String),
+    /// Text that was in double quotes, with the quotes removed
+    Phrase(String),
+    /// `key:value`, e.g. `ext:rs` or `in:"My Documents"`
+    Filter {
+        key: String,
+        value: String,
+    },
+}
+
+/// Token as it was read, before deciding what it is
+struct RawToken {
+    text: String,
+    quoted: bool,
+    /// Byte offset of the first `:` that wasn't quoted or escaped
+    colon: Option<usize>,
+}
+
+// =================================================================
+// ==== Methods
+// =================================================================
+
 impl SearchQuery {
+    /// Splits the query at the first whitespace into a keyword and the search
+    /// text. Queries without whitespace have no keyword.
     pub fn from(query: &str) -> Self {
-        let mut keyword = String::new();
-        let mut search_text = String::new();
-        let mut has_keyword = false;
-
-        for char in query.chars() {
-            if char == ' ' && !has_keyword {
-                has_keyword = true;
-            } else if !has_keyword {
-                keyword += &char.to_string();
+        match query.split_once(char::is_whitespace) {
+            Some((keyword, search_text)) => SearchQuery {
+                keyword: Some(keyword.to_owned()),
+                search_text: search_text.trim().to_owned(),
+            },
+            None => SearchQuery {
+                keyword: None,
+                search_text: query.trim().to_owned(),
+            },
+        }
+    }
+
+    /// Words, phrases and filters of the search text
+    pub fn tokens(&self) -> Vec<QueryToken> {
+        tokenize(&self.search_text)
+    }
+
+    /// Value of the first `key:value` filter with the given key
+    pub fn get_filter(&self, key: &str) -> Option<String> {
+        self.tokens().into_iter().find_map(|token| match token {
+            QueryToken::Filter { key: k, value } if k == key => Some(value),
+            _ => None,
+        })
+    }
+
+    /// Words and phrases of the search text, without the filters
+    pub fn get_terms(&self) -> Vec<String> {
+        self.tokens()
+            .into_iter()
+            .filter_map(|token| match token {
+                QueryToken::Word(text) | QueryToken::Phrase(text) => Some(text),
+                QueryToken::Filter { .. } => None,
+            })
+            .collect()
+    }
+}
+
+impl QueryToken {
+    /// Text of a word or phrase, value of a filter
+    pub fn get_text(&self) -> &str {
+        match self {
+            QueryToken::Word(text) | QueryToken::Phrase(text) => text,
+            QueryToken::Filter { value, .. } => value,
+        }
+    }
+}
+
+/// Splits text into tokens at any whitespace. Double quotes group text into a
+/// phrase and `\` takes the next character literally, also inside quotes. An
+/// unquoted `key:value` is a filter if the key is made of letters, digits, `_`
+/// or `-` and the value isn't empty, so links like `https://…` stay words.
+pub fn tokenize(text: &str) -> Vec<QueryToken> {
+    read_raw_tokens(text)
+        .into_iter()
+        .map(|token| {
+            if let Some(colon) = token.colon {
+                let (key, value) = (&token.text[..colon], &token.text[colon + 1..]);
+
+                if is_filter(key, value) {
+                    return QueryToken::Filter {
+                        key: key.to_owned(),
+                        value: value.to_owned(),
+                    };
+                }
+            }
+
+            if token.quoted {
+                QueryToken::Phrase(token.text)
             } else {
-                search_text += &char.to_string();
+                QueryToken::Word(token.text)
             }
+        })
+        .collect()
+}
+
+fn read_raw_tokens(text: &str) -> Vec<RawToken> {
+    let mut tokens = vec![];
+    let mut current: Option<RawToken> = None;
+    let mut in_quotes = false;
+    let mut chars = text.chars();
+
+    while let Some(char) = chars.next() {
+        if char.is_whitespace() && !in_quotes {
+            tokens.extend(current.take());
+            continue;
         }
 
-        if !has_keyword {
-            search_text = keyword.to_owned();
+        let token = current.get_or_insert_with(|| RawToken {
+            text: String::new(),
+            quoted: false,
+            colon: None,
+        });
+
+        match char {
+            // A trailing backslash has nothing to escape, so it's kept
+            '\\' => token.text.push(chars.next().unwrap_or('\\')),
+            '"' => {
+                in_quotes = !in_quotes;
+                token.quoted = true;
+            }
+            ':' if !in_quotes && token.colon.is_none() => {
+                token.colon = Some(token.text.len());
+                token.text.push(char);
+            }
+            _ => token.text.push(char),
         }
+    }
 
-        search_text = search_text.trim().to_owned();
+    tokens.extend(current);
+    tokens
+}
 
-        SearchQuery {
-            keyword: match has_keyword {
-                true => Some(keyword),
-                false => None,
-            },
-            search_text,
+fn is_filter(key: &str, value: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+        && !value.is_empty()
+        && !value.starts_with("//")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(text: &str) -> QueryToken {
+        QueryToken::Word(text.to_owned())
+    }
+
+    fn filter(key: &str, value: &str) -> QueryToken {
+        QueryToken::Filter {
+            key: key.to_owned(),
+            value: value.to_owned(),
         }
     }
+
+    #[test]
+    fn splits_keyword_at_any_whitespace() {
+        let query = SearchQuery::from("gs\trust  lang ");
+        assert_eq!(query.keyword.as_deref(), Some("gs"));
+        assert_eq!(query.search_text, "rust  lang");
+
+        let query = SearchQuery::from("rust");
+        assert_eq!(query.keyword, None);
+        assert_eq!(query.search_text, "rust");
+    }
+
+    #[test]
+    fn reads_words_phrases_and_filters() {
+        assert_eq!(
+            tokenize(r#"report  "annual plan" ext:pdf in:"My Documents""#),
+            vec![
+                word("report"),
+                QueryToken::Phrase("annual plan".to_owned()),
+                filter("ext", "pdf"),
+                filter("in", "My Documents"),
+            ]
+        );
+    }
+
+    #[test]
+    fn escapes_take_the_next_character_literally() {
+        assert_eq!(
+            tokenize(r#"a\ b \"c ext\:pdf end\"#),
+            vec![word("a b"), word("\"c"), word("ext:pdf"), word("end\\")]
+        );
+    }
+
+    #[test]
+    fn links_and_empty_values_are_words() {
+        assert_eq!(
+            tokenize("https://example.org ext: :pdf"),
+            vec![word("https://example.org"), word("ext:"), word(":pdf")]
+        );
+    }
+
+    #[test]
+    fn finds_filters_and_terms() {
+        let query = SearchQuery::from(r#"files report ext:pdf "q3 sales""#);
+        assert_eq!(query.get_filter("ext").as_deref(), Some("pdf"));
+        assert_eq!(query.get_filter("in"), None);
+        assert_eq!(query.get_terms(), ["report", "q3 sales"]);
+    }
 }
@@ -1,10 +1,13 @@
 use std::{error::Error, fs, path::PathBuf};
 
-use postcard::{from_bytes, to_allocvec};
+use postcard::from_bytes;
 use serde::{Deserialize, Serialize};
 
 use super::paths::get_settings_path;
 
+#[cfg(feature = "host")]
+use postcard::to_allocvec;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Settings {
     #[serde(default = "default_width")]
@@ -78,7 +81,7 @@ pub struct ExtensionValue {
     pub value: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct SearchEngine {
     pub id: usize,
     pub keyword: String,
@@ -208,12 +211,37 @@ pub fn get_default_settings() -> Settings {
 // ===== Methods
 // ===============================================================
 
+impl SearchEngine {
+    /// Query URL with `%s` replaced by the percent-encoded search text
+    pub fn get_search_url(&self, search_text: &str) -> String {
+        self.query
+            .replace("%s", &encode_query_component(search_text))
+    }
+}
+
+fn encode_query_component(text: &str) -> String {
+    let mut encoded = String::new();
+
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b' ' => encoded.push('+'),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+
+    encoded
+}
+
 pub fn get_settings() -> Result<Settings, Box<dyn Error>> {
     let bytes = fs::read(get_settings_path()?)?;
     let settings: Settings = from_bytes(&bytes)?;
     Ok(settings)
 }
 
+#[cfg(feature = "host")]
 pub fn write_settings(settings: &Settings) -> Result<(), Box<dyn Error>> {
     let bytes = to_allocvec(settings)?;
     Ok(fs::write(get_settings_path()?, &bytes)?)
@@ -243,7 +271,7 @@ pub fn get_bool_extension_setting(
     setting_id: &str,
 ) -> Result<bool, Box<dyn Error>> {
     let value = get_extension_setting(extension_id, setting_id)?;
-    Ok(if value == "true" { true } else { false })
+    Ok(value == "true")
 }
 
 #[cfg(feature = "extension")]
@@ -0,0 +1,339 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use postcard::to_allocvec;
+
+use super::{
+    actions::{ActionType, ResultAction},
+    api::{
+        dispatch, take_pending_results, ActionResponse, Extension, ExtensionRequest,
+        ExtensionResponse, FormResult, QueryContext,
+    },
+    paths::{get_config_dir, get_local_dir, get_settings_path, FAKE_ROOT},
+    search_results::SearchResult,
+    settings::{get_default_settings, ExtensionValue, Settings},
+};
+
+/// Gives each harness its own dir when tests run in parallel
+static NEXT_HARNESS: AtomicU64 = AtomicU64::new(0);
+
+/// Puts back the previous fake root on drop, also when a handler panics
+struct FakeRootGuard {
+    previous: Option<PathBuf>,
+}
+
+/// Runs an extension's handlers in the test process instead of spawning it.
+/// Settings and dirs point into a throwaway dir that is removed on drop, so
+/// tests don't see or touch the real launcher files.
+///
+/// Dirs are only faked on the thread that calls the harness. Results sent
+/// with `send_partial_results` are merged into the response like the host does.
+pub struct TestHarness<E: Extension> {
+    pub extension: E,
+    root: PathBuf,
+    settings: Settings,
+}
+
+// =================================================================
+// ==== Methods
+// =================================================================
+
+impl<E: Extension> TestHarness<E> {
+    pub fn new(extension: E) -> Self {
+        let root = std::env::temp_dir().join(format!(
+            "tigris-test-{}-{}",
+            std::process::id(),
+            NEXT_HARNESS.fetch_add(1, Ordering::SeqCst)
+        ));
+
+        let harness = Self {
+            extension,
+            root,
+            settings: get_default_settings(),
+        };
+
+        harness.write_settings();
+        harness
+    }
+
+    pub fn set_settings(mut self, settings: Settings) -> Self {
+        self.settings = settings;
+        self.write_settings();
+        self
+    }
+
+    /// Sets the value returned by `get_extension_setting`
+    pub fn set_extension_setting(
+        mut self,
+        extension_id: &str,
+        setting_id: &str,
+        value: &str,
+    ) -> Self {
+        let values = &mut self.settings.extension_values;
+        values.retain(|v| !(v.extension_id == extension_id && v.setting_id == setting_id));
+
+        values.push(ExtensionValue {
+            extension_id: extension_id.to_owned(),
+            setting_id: setting_id.to_owned(),
+            value: value.to_owned(),
+        });
+
+        self.write_settings();
+        self
+    }
+
+    /// Fake local data dir, e.g. to place files the extension reads
+    pub fn get_local_dir(&self) -> PathBuf {
+        self.with_fake_dirs(|| get_local_dir().expect("Error creating fake local dir"))
+    }
+
+    pub fn get_config_dir(&self) -> PathBuf {
+        self.with_fake_dirs(|| get_config_dir().expect("Error creating fake config dir"))
+    }
+
+    pub fn get_results(&self, search_text: &str) -> ExtensionResponse {
+        self.send(ExtensionRequest::new_get_results_request(search_text))
+    }
+
+    pub fn get_results_with_context(
+        &self,
+        search_text: &str,
+        context: &QueryContext,
+    ) -> ExtensionResponse {
+        self.send(ExtensionRequest::new_get_results_request_with_context(
+            search_text,
+            context,
+        ))
+    }
+
+    pub fn run_action(&self, action: &str, args: &Vec<String>) -> Result<ActionResponse, String> {
+        self.send(ExtensionRequest::new_run_extension_action_request(
+            action, args,
+        ))
+        .into_action_response()
+    }
+
+    /// Runs an action as if a result of `caller_id` triggered it. Doesn't check
+    /// the manifest, that's up to the host.
+    pub fn run_action_from(
+        &self,
+        caller_id: &str,
+        action: &str,
+        args: &Vec<String>,
+    ) -> Result<ActionResponse, String> {
+        self.send(ExtensionRequest::new_cross_extension_action_request(
+            caller_id, action, args,
+        ))
+        .into_action_response()
+    }
+
+    pub fn send_form_results(
+        &self,
+        form_id: &str,
+        results: &Vec<FormResult>,
+        args: &Vec<String>,
+    ) -> Result<ActionResponse, String> {
+        self.send(ExtensionRequest::new_form_results_request(
+            form_id, results, args,
+        ))
+        .into_action_response()
+    }
+
+    pub fn run_job(&self, job_id: &str) -> Result<(), String> {
+        match self.send(ExtensionRequest::new_run_job_request(job_id)) {
+            ExtensionResponse::Error { message, .. } => Err(message),
+            _ => Ok(()),
+        }
+    }
+
+    /// Calls the handler for the request the same way `run` does
+    pub fn send(&self, request: ExtensionRequest) -> ExtensionResponse {
+        self.with_fake_dirs(|| {
+            let response = dispatch(&self.extension, request);
+            response.merge_partial_results(take_pending_results())
+        })
+    }
+
+    fn with_fake_dirs<T>(&self, f: impl FnOnce() -> T) -> T {
+        let previous = FAKE_ROOT.with(|root| root.replace(Some(self.root.to_owned())));
+        let _guard = FakeRootGuard { previous };
+
+        f()
+    }
+
+    fn write_settings(&self) {
+        self.with_fake_dirs(|| {
+            let bytes = to_allocvec(&self.settings).expect("Error encoding settings");
+            let path = get_settings_path().expect("Error getting fake settings path");
+            fs::write(path, bytes).expect("Error writing fake settings");
+        })
+    }
+}
+
+impl<E: Extension> Drop for TestHarness<E> {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+impl Drop for FakeRootGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        FAKE_ROOT.with(|root| root.replace(previous));
+    }
+}
+
+// =================================================================
+// ==== Assertions
+// =================================================================
+
+#[track_caller]
+pub fn assert_results_len(response: &ExtensionResponse, len: usize) {
+    assert_not_error(response);
+
+    let results = response.get_results();
+    assert_eq!(
+        results.len(),
+        len,
+        "Expected {len} results, got {}: {:#?}",
+        results.len(),
+        results
+    );
+}
+
+/// Returns the first result with the given title
+#[track_caller]
+pub fn assert_has_result<'a>(response: &'a ExtensionResponse, title: &str) -> &'a SearchResult {
+    assert_not_error(response);
+
+    response
+        .get_results()
+        .iter()
+        .find(|result| result.title == title)
+        .unwrap_or_else(|| {
+            panic!(
+                "No result titled {title:?} in {:?}",
+                get_titles(response.get_results())
+            )
+        })
+}
+
+#[track_caller]
+pub fn assert_no_results(response: &ExtensionResponse) {
+    assert_not_error(response);
+
+    assert!(
+        response.get_results().is_empty(),
+        "Expected no results, got {:?}",
+        get_titles(response.get_results())
+    );
+}
+
+#[track_caller]
+pub fn assert_titles(response: &ExtensionResponse, titles: &[&str]) {
+    assert_not_error(response);
+    assert_eq!(get_titles(response.get_results()), titles);
+}
+
+#[track_caller]
+pub fn assert_not_error(response: &ExtensionResponse) {
+    if let ExtensionResponse::Error { message, details } = response {
+        panic!("Extension returned an error: {message} {details:?}");
+    }
+}
+
+/// Checks that the response is an error containing `text`
+#[track_caller]
+pub fn assert_error_contains(response: &ExtensionResponse, text: &str) {
+    match response {
+        ExtensionResponse::Error { message, .. } => assert!(
+            message.contains(text),
+            "Expected error containing {text:?}, got {message:?}"
+        ),
+        other => panic!("Expected an error, got {other:?}"),
+    }
+}
+
+/// Returns the action of the result, checking it has the given type
+#[track_caller]
+pub fn assert_action_type(result: &SearchResult, action_type: ActionType) -> &ResultAction {
+    let action = result
+        .action
+        .as_ref()
+        .unwrap_or_else(|| panic!("Result {:?} has no action", result.title));
+
+    assert_eq!(
+        std::mem::discriminant(&action.action_type),
+        std::mem::discriminant(&action_type),
+        "Expected {action_type:?} action on {:?}, got {:?}",
+        result.title,
+        action.action_type
+    );
+
+    action
+}
+
+#[track_caller]
+pub fn assert_copies_text(result: &SearchResult, text: &str) {
+    let action = assert_action_type(result, ActionType::CopyText);
+    let copied = action.copy_text_action.as_ref().map(|a| a.text.as_str());
+    assert_eq!(copied, Some(text));
+}
+
+#[track_caller]
+pub fn assert_opens_link(result: &SearchResult, link: &str) {
+    let action = assert_action_type(result, ActionType::OpenLink);
+    let opened = action.open_link_action.as_ref().map(|a| a.link.as_str());
+    assert_eq!(opened, Some(link));
+}
+
+#[track_caller]
+pub fn assert_opens_app(result: &SearchResult, path: &Path) {
+    let action = assert_action_type(result, ActionType::OpenApp);
+    let opened = action.open_app_action.as_ref().map(|a| a.path.as_path());
+    assert_eq!(opened, Some(path));
+}
+
+/// Returns the args of a run extension action
+#[track_caller]
+pub fn assert_runs_extension_action<'a>(result: &'a SearchResult, action: &str) -> &'a Vec<String> {
+    let result_action = assert_action_type(result, ActionType::RunExtension);
+    let run_action = result_action
+        .run_extension_action
+        .as_ref()
+        .unwrap_or_else(|| panic!("Result {:?} has no run extension action", result.title));
+
+    assert_eq!(run_action.extension_action, action);
+    &run_action.args
+}
+
+/// Checks the result replaces the query with `text`
+#[track_caller]
+pub fn assert_result_sets_query(result: &SearchResult, text: &str) {
+    let action = assert_action_type(result, ActionType::SetQuery);
+    let query = action.set_query_action.as_ref().map(|a| a.text.as_str());
+    assert_eq!(query, Some(text));
+}
+
+#[track_caller]
+pub fn assert_closes(response: &Result<ActionResponse, String>) {
+    match response {
+        Ok(ActionResponse::Close) => {}
+        other => panic!("Expected the launcher to close, got {other:?}"),
+    }
+}
+
+#[track_caller]
+pub fn assert_sets_query(response: &Result<ActionResponse, String>, query: &str) {
+    match response {
+        Ok(ActionResponse::SetQuery { query: set_query }) => assert_eq!(set_query, query),
+        other => panic!("Expected the query to be set to {query:?}, got {other:?}"),
+    }
+}
+
+fn get_titles(results: &[SearchResult]) -> Vec<&str> {
+    results.iter().map(|result| result.title.as_str()).collect()
+}
@@ -10,10 +10,27 @@ pub fn on_wayland() -> bool {
     false
 }
 
+/// Locale of the user from the usual environment variables, like `en_US`
+pub fn get_locale() -> String {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .map(|value| {
+            // Drop the encoding and modifier, e.g. `pt_PT.UTF-8@euro`
+            value
+                .split(['.', '@'])
+                .next()
+                .unwrap_or_default()
+                .to_owned()
+        })
+        .find(|locale| !locale.is_empty() && locale != "C" && locale != "POSIX")
+        .unwrap_or_else(|| String::from("en_US"))
+}
+
 pub fn send_notification(title: &str, description: &str) {
     Notification::new()
-        .summary(&title)
-        .body(&description)
+        .summary(title)
+        .body(description)
         .icon("tigris")
         .show()
         .expect("Error sending notification");
@@ -0,0 +1,423 @@
+use std::{fmt::Display, path::Path};
+
+use super::{
+    actions::{ActionType, ResultAction},
+    api::{ActionResponse, ExtensionResponse},
+    protocol::MAX_FRAME_BYTES,
+    search_results::SearchResult,
+};
+
+pub const DEFAULT_MAX_RESULTS: usize = 500;
+
+/// Colors of the theme that can be used instead of a hex code
+const THEME_COLORS: [&str; 11] = [
+    "accent",
+    "on_accent",
+    "danger",
+    "on_danger",
+    "background",
+    "secondary_background",
+    "tertiary_background",
+    "text",
+    "secondary_text",
+    "tertiary_text",
+    "disabled_text",
+];
+
+/// How much output the host accepts from an extension
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutputLimits {
+    /// Largest response or stream frame, in bytes
+    pub max_frame_bytes: usize,
+    /// Most results kept for a single request, streamed batches included
+    pub max_results: usize,
+}
+
+/// Result that was removed from an extension's output
+#[derive(Debug, Clone, PartialEq)]
+pub struct DroppedResult {
+    /// Position of the result in the extension's output
+    pub index: usize,
+    pub title: String,
+    pub reason: String,
+}
+
+/// What was removed from the output of a request and why
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ValidationReport {
+    pub dropped: Vec<DroppedResult>,
+    /// Valid results over `max_results` that were cut off
+    pub truncated: usize,
+}
+
+/// Checks the results of a request, keeping count across streamed batches
+#[derive(Debug, Clone)]
+pub struct ResultValidator {
+    limits: OutputLimits,
+    seen: usize,
+    kept: usize,
+    report: ValidationReport,
+}
+
+// =================================================================
+// ==== Methods
+// =================================================================
+
+impl OutputLimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_max_frame_bytes(mut self, max_frame_bytes: usize) -> Self {
+        self.max_frame_bytes = max_frame_bytes;
+        self
+    }
+
+    pub fn set_max_results(mut self, max_results: usize) -> Self {
+        self.max_results = max_results;
+        self
+    }
+}
+
+impl Default for OutputLimits {
+    fn default() -> Self {
+        Self {
+            max_frame_bytes: MAX_FRAME_BYTES,
+            max_results: DEFAULT_MAX_RESULTS,
+        }
+    }
+}
+
+impl ValidationReport {
+    pub fn is_empty(&self) -> bool {
+        self.dropped.is_empty() && self.truncated == 0
+    }
+}
+
+impl ResultValidator {
+    pub fn new(limits: &OutputLimits) -> Self {
+        Self {
+            limits: limits.to_owned(),
+            seen: 0,
+            kept: 0,
+            report: ValidationReport::default(),
+        }
+    }
+
+    /// Drops invalid results and the ones past the limit
+    pub fn filter(&mut self, results: Vec<SearchResult>) -> Vec<SearchResult> {
+        let mut valid = vec![];
+
+        for result in results {
+            let index = self.seen;
+            self.seen += 1;
+
+            if let Err(reason) = validate_result(&result) {
+                self.report.dropped.push(DroppedResult {
+                    index,
+                    title: result.title,
+                    reason,
+                });
+            } else if self.kept >= self.limits.max_results {
+                self.report.truncated += 1;
+            } else {
+                self.kept += 1;
+                valid.push(result);
+            }
+        }
+
+        valid
+    }
+
+    /// Filters the results carried by a response, if it has any
+    pub fn filter_response(&mut self, response: ExtensionResponse) -> ExtensionResponse {
+        match response {
+            ExtensionResponse::Results(results) => ExtensionResponse::Results(self.filter(results)),
+            ExtensionResponse::CachedResults { results, ttl_secs } => {
+                ExtensionResponse::CachedResults {
+                    results: self.filter(results),
+                    ttl_secs,
+                }
+            }
+            ExtensionResponse::Action(ActionResponse::ShowResults(results)) => {
+                ExtensionResponse::Action(ActionResponse::ShowResults(self.filter(results)))
+            }
+            ExtensionResponse::Action(ActionResponse::OpenForm(form)) => {
+                if let Err(reason) = validate_form_ids(&form.extension_id, &form.form_id) {
+                    return ExtensionResponse::new_error(&reason);
+                }
+
+                ExtensionResponse::Action(ActionResponse::OpenForm(form))
+            }
+            response => response,
+        }
+    }
+
+    pub fn get_report(&self) -> &ValidationReport {
+        &self.report
+    }
+
+    pub fn into_report(self) -> ValidationReport {
+        self.report
+    }
+}
+
+/// Checks that a result can be shown and its action can be run
+pub fn validate_result(result: &SearchResult) -> Result<(), String> {
+    if result.title.trim().is_empty() {
+        return Err("Title is empty".to_string());
+    }
+
+    if let Some(icon_path) = &result.icon_path {
+        if icon_path.trim().is_empty() {
+            return Err("Icon path is empty".to_string());
+        }
+    }
+
+    if let Some(icon_color) = &result.icon_color {
+        if !is_valid_color(icon_color) {
+            return Err(format!("Invalid icon color {icon_color:?}"));
+        }
+    }
+
+    if let Some(action) = &result.action {
+        validate_action(action)?;
+    }
+
+    Ok(())
+}
+
+/// Checks that the payload for the action type is there and makes sense
+pub fn validate_action(action: &ResultAction) -> Result<(), String> {
+    match action.action_type {
+        ActionType::CopyText => {
+            let action = action
+                .copy_text_action
+                .as_ref()
+                .ok_or("Missing copy text action")?;
+
+            if action.text.is_empty() {
+                return Err("Copy text action has no text".to_string());
+            }
+        }
+        ActionType::CopyImage => {
+            let action = action
+                .copy_image_action
+                .as_ref()
+                .ok_or("Missing copy image action")?;
+
+            validate_path(&action.image_path)?;
+        }
+        ActionType::OpenLink => {
+            let action = action
+                .open_link_action
+                .as_ref()
+                .ok_or("Missing open link action")?;
+
+            if !is_valid_link(&action.link) {
+                return Err(format!("Invalid link {:?}", action.link));
+            }
+        }
+        ActionType::OpenApp => {
+            let action = action
+                .open_app_action
+                .as_ref()
+                .ok_or("Missing open app action")?;
+
+            validate_path(&action.path)?;
+
+            if action.path.extension().is_none_or(|e| e != "desktop") {
+                return Err(format!("{} is not a desktop file", action.path.display()));
+            }
+        }
+        ActionType::OpenForm => {
+            let action = action
+                .open_form_action
+                .as_ref()
+                .ok_or("Missing open form action")?;
+
+            validate_form_ids(&action.extension_id, &action.form_id)?;
+        }
+        ActionType::RunExtension => {
+            let action = action
+                .run_extension_action
+                .as_ref()
+                .ok_or("Missing run extension action")?;
+
+            if action.extension_id.is_empty() || action.extension_action.is_empty() {
+                return Err("Run extension action has no extension or action".to_string());
+            }
+        }
+        ActionType::OpenSettings => {}
+        ActionType::SetQuery => {
+            action
+                .set_query_action
+                .as_ref()
+                .ok_or("Missing set query action")?;
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_form_ids(extension_id: &str, form_id: &str) -> Result<(), String> {
+    if extension_id.is_empty() || form_id.is_empty() {
+        return Err("Form has no extension or form id".to_string());
+    }
+
+    Ok(())
+}
+
+fn validate_path(path: &Path) -> Result<(), String> {
+    match path.to_str() {
+        Some("") => Err("Path is empty".to_string()),
+        Some(_) => Ok(()),
+        None => Err(format!("Path {} is not valid UTF-8", path.display())),
+    }
+}
+
+/// Accepts `#rgb`, `#rgba`, `#rrggbb`, `#rrggbbaa` and theme color names
+fn is_valid_color(color: &str) -> bool {
+    if THEME_COLORS.contains(&color) {
+        return true;
+    }
+
+    match color.strip_prefix('#') {
+        Some(hex) => {
+            [3, 4, 6, 8].contains(&hex.len()) && hex.chars().all(|c| c.is_ascii_hexdigit())
+        }
+        None => false,
+    }
+}
+
+/// Links need a scheme as in RFC 3986, which starts with a letter. That also
+/// keeps them from being read as an option by `xdg-open`.
+pub(crate) fn is_valid_link(link: &str) -> bool {
+    let Some((scheme, rest)) = link.split_once(':') else {
+        return false;
+    };
+
+    scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+        && !rest.is_empty()
+        && !link.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+impl Display for ValidationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for dropped in &self.dropped {
+            writeln!(
+                f,
+                "Dropped result {} ({:?}): {}",
+                dropped.index, dropped.title, dropped.reason
+            )?;
+        }
+
+        if self.truncated > 0 {
+            writeln!(f, "Dropped {} results over the limit", self.truncated)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_results(titles: &[&str]) -> Vec<SearchResult> {
+        titles
+            .iter()
+            .map(|title| SearchResult::new(title))
+            .collect()
+    }
+
+    fn get_titles(results: &[SearchResult]) -> Vec<&str> {
+        results.iter().map(|result| result.title.as_str()).collect()
+    }
+
+    #[test]
+    fn links_need_a_scheme_starting_with_a_letter() {
+        assert!(is_valid_link("https://example.com/a?b=c"));
+        assert!(is_valid_link("mailto:a@b"));
+        assert!(is_valid_link("git+ssh://host/repo"));
+
+        assert!(!is_valid_link("-foo:bar"));
+        assert!(!is_valid_link("1http:x"));
+        assert!(!is_valid_link("http:"));
+        assert!(!is_valid_link("no scheme"));
+        assert!(!is_valid_link(":x"));
+    }
+
+    #[test]
+    fn links_with_whitespace_or_control_characters_are_rejected() {
+        assert!(!is_valid_link("https://example.com/a b"));
+        assert!(!is_valid_link("https://example.com/\ta"));
+        assert!(!is_valid_link("https://example.com/\na"));
+        assert!(!is_valid_link("https://example.com/\u{7f}"));
+        assert!(!is_valid_link("https://example.com/\0"));
+    }
+
+    #[test]
+    fn colors_are_hex_codes_or_theme_names() {
+        for color in ["#abc", "#abcd", "#a1b2c3", "#A1B2C3D4"] {
+            assert!(is_valid_color(color), "{color}");
+        }
+
+        for color in [
+            "#ab",
+            "#abcde",
+            "#abcdefg",
+            "#abcdef123",
+            "#ggg",
+            "abc",
+            "#",
+        ] {
+            assert!(!is_valid_color(color), "{color}");
+        }
+
+        assert!(is_valid_color("accent"));
+        assert!(is_valid_color("disabled_text"));
+        assert!(!is_valid_color("red"));
+        assert!(!is_valid_color("Accent"));
+    }
+
+    #[test]
+    fn truncation_is_counted_across_batches() {
+        let limits = OutputLimits::new().set_max_results(3);
+        let mut validator = ResultValidator::new(&limits);
+
+        let first = validator.filter(get_results(&["a", "", "b"]));
+        assert_eq!(get_titles(&first), ["a", "b"]);
+
+        let second = validator.filter(get_results(&["c", "d", " "]));
+        assert_eq!(get_titles(&second), ["c"]);
+
+        let third = validator.filter(get_results(&["e"]));
+        assert!(third.is_empty());
+
+        let report = validator.into_report();
+        assert_eq!(report.truncated, 2);
+
+        let dropped: Vec<usize> = report.dropped.iter().map(|d| d.index).collect();
+        assert_eq!(dropped, [1, 5]);
+    }
+
+    #[test]
+    fn invalid_results_are_dropped_with_a_reason() {
+        let mut validator = ResultValidator::new(&OutputLimits::new());
+        let results = vec![
+            SearchResult::new("ok").set_icon_color("#fff"),
+            SearchResult::new("bad color").set_icon_color("red"),
+        ];
+
+        assert_eq!(get_titles(&validator.filter(results)), ["ok"]);
+
+        let report = validator.get_report();
+        assert_eq!(report.dropped.len(), 1);
+        assert_eq!(report.dropped[0].title, "bad color");
+        assert!(report.dropped[0].reason.contains("icon color"));
+    }
+}
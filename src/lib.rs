@@ -1 +1,6 @@
-pub mod features;
\ No newline at end of file
+pub mod features;
+
+pub mod prelude;
+
+#[cfg(feature = "extension")]
+pub use features::api::run;
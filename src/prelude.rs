@@ -0,0 +1,62 @@
+//! Common imports for the launcher and for extensions.
+
+// Shared
+pub use crate::features::{
+    actions::{
+        ActionType, CopyImageAction, CopyTextAction, Field, FieldType, FieldValidation,
+        FileSystemField, OpenAppAction, OpenFormAction, OpenLinkAction, ResultAction,
+        RunExtensionAction, SelectField, SelectFieldValue, SetQueryAction, SliderField,
+        SwitchField, TextAreaField, TextField,
+    },
+    api::{
+        ActionResponse, ExtensionRequest, ExtensionResponse, FormResult, FormResultsRequest,
+        GetResultsRequest, QueryContext, RequestType, RunActionRequest, RunJobRequest,
+    },
+    extensions::{get_extension_dir, CachePolicy},
+    forms::{read_form, remove_form, take_form_results, write_form, write_form_results},
+    matching::{filter_results, fuzzy_match, match_results, FuzzyMatch, ResultMatch},
+    paths::{get_assets_dir, get_icons_dir, get_local_dir},
+    protocol::{Encoding, PROTOCOL_VERSION},
+    sandbox::SandboxPolicy,
+    search::{tokenize, QueryToken, SearchQuery},
+    search_results::SearchResult,
+    settings::{get_settings, Settings},
+    utils::send_notification,
+};
+
+// Host
+#[cfg(feature = "host")]
+pub use crate::features::{
+    actions::{get_action_query, run_action},
+    cache::{ResultCache, SharedCache},
+    cancellation::{CancelToken, SearchTicket, SearchTracker},
+    control::{send_control_command, ControlCommand, ControlResponse, ControlServer},
+    daemon::ExtensionDaemon,
+    extensions::get_extensions,
+    frecency::{get_result_id, FrecencyStore},
+    jobs::{JobScheduler, JobStatus},
+    router::{KeywordConflict, KeywordOwner, KeywordRouter, Route},
+    runner::{run_extension_action, ExtensionRunner, RunnerError},
+    settings::{get_default_settings, write_settings},
+    validation::{OutputLimits, ValidationReport},
+};
+
+// Extension
+#[cfg(feature = "extension")]
+pub use crate::features::{
+    api::{
+        get_request, is_request_cancelled, return_error, return_response, return_search_results,
+        run, send_partial_results, Extension,
+    },
+    settings::{get_bool_extension_setting, get_extension_setting, get_usize_extension_setting},
+};
+
+// Async Extension
+#[cfg(feature = "tokio")]
+pub use crate::features::async_api::{
+    get_request_async, return_search_results_async, run_async, write_response_async, AsyncExtension,
+};
+
+// Testing
+#[cfg(feature = "testing")]
+pub use crate::features::testing::TestHarness;